@@ -0,0 +1,18 @@
+// Decoders for account data fetched over RPC.
+
+use anchor_lang::{error::ErrorCode, Discriminator, Result};
+use soondex::PoolInfo;
+
+// `PoolInfo` from raw account data. Pools still in the original Borsh layout
+// decode with `soondex::migrate::BaselinePoolInfo::decode` instead, and have to
+// be migrated with `instructions::migrate_pool` before anything writes to them.
+pub fn decode_pool_info(data: &[u8]) -> Result<PoolInfo> {
+    if data.len() < 8 || data[..8] != PoolInfo::DISCRIMINATOR {
+        return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+    }
+    if data.len() != PoolInfo::SPACE {
+        return Err(ErrorCode::AccountDidNotDeserialize.into());
+    }
+    // RPC buffers carry no alignment guarantee, so copy out
+    Ok(bytemuck::pod_read_unaligned(&data[8..]))
+}
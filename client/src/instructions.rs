@@ -0,0 +1,307 @@
+// Instruction builders.
+//
+// Accounts come from the program's own `accounts` structs and data from its
+// `instruction` structs, so these break at compile time when a context
+// changes. Optional accounts are filled in from the decoded `PoolInfo`: the
+// TWAMM accounts while the pool has long-term orders, the config and creator
+// fee vault when it pays a creator fee, and the trader's allowlist entry or
+// gate token in permissioned pools.
+
+use anchor_lang::{
+    solana_program::{instruction::Instruction, sysvar},
+    system_program, InstructionData, ToAccountMetas,
+};
+use anchor_lang::prelude::{AccountMeta, Pubkey};
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use anchor_spl::{metadata, token};
+use soondex::migrate::BaselinePoolInfo;
+use soondex::permission::AccessMode;
+use soondex::{accounts, instruction, BuyInstructionData, CurveType, PoolInfo, SellInstructionData, ID};
+
+use crate::pda;
+
+// Referral paid out of a swap fee
+#[derive(Debug, Clone, Copy)]
+pub struct Referral {
+    // Token A account of the referrer
+    pub token_account: Pubkey,
+    // Share of the swap fee, in bps of the fee
+    pub bps: u64,
+}
+
+fn pool_key(pool: &PoolInfo) -> Pubkey {
+    pda::pool_address(&pool.mint_b).0
+}
+
+// (twamm, twamm_vault_a, twamm_vault_b)
+fn twamm_accounts(pool_key: &Pubkey, pool: &PoolInfo) -> (Option<Pubkey>, Option<Pubkey>, Option<Pubkey>) {
+    if pool.long_term_orders == 0 {
+        return (None, None, None);
+    }
+    let twamm = pda::twamm_address(pool_key).0;
+    (
+        Some(twamm),
+        Some(pda::twamm_vault_address(&twamm, &pool.mint_a).0),
+        Some(pda::twamm_vault_address(&twamm, &pool.mint_b).0),
+    )
+}
+
+// (allowlist_entry, gate_token)
+fn access_accounts(pool_key: &Pubkey, pool: &PoolInfo, trader: &Pubkey) -> (Option<Pubkey>, Option<Pubkey>) {
+    match pool.access_mode() {
+        AccessMode::Open => (None, None),
+        AccessMode::Allowlist => (Some(pda::allowlist_address(pool_key, trader).0), None),
+        AccessMode::TokenGate => (None, Some(get_associated_token_address(trader, &pool.gate_mint))),
+    }
+}
+
+fn creator_fee_vault(pool_key: &Pubkey, pool: &PoolInfo) -> Option<Pubkey> {
+    (pool.creator_fee_bps > 0).then(|| pda::creator_fee_vault_address(pool_key).0)
+}
+
+// (config, remaining accounts, referral bps). The config caps both referral
+// and creator fees.
+fn fee_accounts(pool: &PoolInfo, referral: &Option<Referral>) -> (Option<Pubkey>, Vec<AccountMeta>, u64) {
+    let config = (referral.is_some() || pool.creator_fee_bps > 0).then(|| pda::config_address().0);
+    match referral {
+        Some(referral) => (config, vec![AccountMeta::new(referral.token_account, false)], referral.bps),
+        None => (config, vec![], 0),
+    }
+}
+
+// `initialize_pool` for a new pool of `mint_a` and `mint_b`. `pool_count` is
+// the registry's current count, which indexes the new registry entry.
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_pool(
+    user: &Pubkey,
+    mint_a: &Pubkey,
+    mint_b: &Pubkey,
+    pool_count: u64,
+    fee: u64,
+    curve_type: CurveType,
+    amp: u64,
+    weight_a: u64,
+) -> Instruction {
+    let pool = pda::pool_address(mint_b).0;
+    let lp_mint = pda::lp_mint_address(&pool).0;
+
+    let accounts = accounts::InitializePool {
+        pool,
+        mint_a: *mint_a,
+        mint_b: *mint_b,
+        pool_token_a: pda::vault_address(&pool, mint_a).0,
+        pool_token_b: pda::vault_address(&pool, mint_b).0,
+        lp_mint,
+        lp_mint_metadata: pda::metadata_address(&lp_mint).0,
+        mint_a_metadata: pda::metadata_address(mint_a).0,
+        mint_b_metadata: pda::metadata_address(mint_b).0,
+        registry: pda::registry_address().0,
+        registry_entry: pda::registry_entry_address(pool_count).0,
+        user: *user,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+        metadata_program: metadata::ID,
+        rent: sysvar::rent::ID,
+    };
+
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction::InitializePool { fee, curve_type, amp, weight_a }.data(),
+    }
+}
+
+// `buy`: spend `amount` of token A for token B. `slippage` is in bps.
+pub fn buy(pool: &PoolInfo, user: &Pubkey, amount: u64, slippage: u64, referral: Option<Referral>) -> Instruction {
+    let pool_key = pool_key(pool);
+    let (twamm, twamm_vault_a, twamm_vault_b) = twamm_accounts(&pool_key, pool);
+    let (allowlist_entry, gate_token) = access_accounts(&pool_key, pool, user);
+    let (config, remaining_accounts, referral_bps) = fee_accounts(pool, &referral);
+
+    let accounts = accounts::BuyInstruction {
+        pool: pool_key,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        pool_token_a_ata: pool.vault_a,
+        pool_token_b_ata: pool.vault_b,
+        user_token_a_ata: get_associated_token_address(user, &pool.mint_a),
+        user_token_b_ata: get_associated_token_address(user, &pool.mint_b),
+        config,
+        creator_fee_vault: creator_fee_vault(&pool_key, pool),
+        twamm,
+        twamm_vault_a,
+        twamm_vault_b,
+        allowlist_entry,
+        gate_token,
+        user: *user,
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+    };
+    let mut metas = accounts.to_account_metas(None);
+    metas.extend(remaining_accounts);
+
+    let swap = BuyInstructionData { amount, slippage, is_buy: true, referral_bps };
+    Instruction {
+        program_id: ID,
+        accounts: metas,
+        data: instruction::Buy { swap }.data(),
+    }
+}
+
+// `sell`: spend `amount` of token B for token A. `slippage` is in bps.
+pub fn sell(pool: &PoolInfo, user: &Pubkey, amount: u64, slippage: u64, referral: Option<Referral>) -> Instruction {
+    let pool_key = pool_key(pool);
+    let (twamm, twamm_vault_a, twamm_vault_b) = twamm_accounts(&pool_key, pool);
+    let (allowlist_entry, gate_token) = access_accounts(&pool_key, pool, user);
+    let (config, remaining_accounts, referral_bps) = fee_accounts(pool, &referral);
+
+    // The sell context names the pool's mints and vaults the other way round
+    let accounts = accounts::SellInstruction {
+        pool: pool_key,
+        mint_a: pool.mint_b,
+        mint_b: pool.mint_a,
+        pool_token_a_ata: pool.vault_b,
+        pool_token_b_ata: pool.vault_a,
+        user_token_a_ata: get_associated_token_address(user, &pool.mint_b),
+        user_token_b_ata: get_associated_token_address(user, &pool.mint_a),
+        config,
+        creator_fee_vault: creator_fee_vault(&pool_key, pool),
+        twamm,
+        twamm_vault_a,
+        twamm_vault_b,
+        allowlist_entry,
+        gate_token,
+        user: *user,
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+    };
+    let mut metas = accounts.to_account_metas(None);
+    metas.extend(remaining_accounts);
+
+    let swap = SellInstructionData { amount, slippage, is_buy: false, referral_bps };
+    Instruction {
+        program_id: ID,
+        accounts: metas,
+        data: instruction::Sell { swap }.data(),
+    }
+}
+
+// `add_liquidity` from the user's token A and B accounts. With
+// `track_position` the user's `LpPosition`, which must already exist, records
+// the deposit.
+pub fn add_liquidity(
+    pool: &PoolInfo,
+    user: &Pubkey,
+    amount_a: u64,
+    amount_b: u64,
+    track_position: bool,
+) -> Instruction {
+    let pool_key = pool_key(pool);
+    let (twamm, twamm_vault_a, twamm_vault_b) = twamm_accounts(&pool_key, pool);
+    let (allowlist_entry, gate_token) = access_accounts(&pool_key, pool, user);
+
+    let accounts = accounts::AddLiquidity {
+        pool: pool_key,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        user_token_a: get_associated_token_address(user, &pool.mint_a),
+        user_token_b: get_associated_token_address(user, &pool.mint_b),
+        pool_token_a: pool.vault_a,
+        pool_token_b: pool.vault_b,
+        lp_mint: pool.lp_mint,
+        user_lp_token_account: get_associated_token_address(user, &pool.lp_mint),
+        twamm,
+        twamm_vault_a,
+        twamm_vault_b,
+        allowlist_entry,
+        gate_token,
+        lp_position: track_position.then(|| pda::lp_position_address(&pool_key, user).0),
+        user: *user,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+        rent: sysvar::rent::ID,
+    };
+
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction::AddLiquidity { amount_one: amount_a, amount_two: amount_b }.data(),
+    }
+}
+
+// `remove_liquidity`: burn `liquidity` LP for token A and B
+pub fn remove_liquidity(pool: &PoolInfo, user: &Pubkey, liquidity: u64, track_position: bool) -> Instruction {
+    let pool_key = pool_key(pool);
+    let (twamm, twamm_vault_a, twamm_vault_b) = twamm_accounts(&pool_key, pool);
+
+    let accounts = accounts::RemoveLiquidity {
+        pool: pool_key,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        pool_token_a: pool.vault_a,
+        pool_token_b: pool.vault_b,
+        lp_mint: pool.lp_mint,
+        user_token_a: get_associated_token_address(user, &pool.mint_a),
+        user_token_b: get_associated_token_address(user, &pool.mint_b),
+        user_lp_token_account: get_associated_token_address(user, &pool.lp_mint),
+        twamm,
+        twamm_vault_a,
+        twamm_vault_b,
+        lp_position: track_position.then(|| pda::lp_position_address(&pool_key, user).0),
+        user: *user,
+        token_program: token::ID,
+        system_program: system_program::ID,
+        associated_token_program: associated_token::ID,
+        rent: sysvar::rent::ID,
+    };
+
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction::RemoveLiquidity { liquidity }.data(),
+    }
+}
+
+// `migrate_pool` then `migrate_vaults` for a pool still in the original Borsh
+// layout, to send ahead of any other instruction on the pool
+pub fn migrate_pool(pool: &BaselinePoolInfo, payer: &Pubkey) -> Vec<Instruction> {
+    let pool_key = pda::pool_address(&pool.mint_b).0;
+
+    let migrate_pool = accounts::MigratePool {
+        pool: pool_key,
+        payer: *payer,
+        system_program: system_program::ID,
+    };
+    let migrate_vaults = accounts::MigrateVaults {
+        pool: pool_key,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        pool_token_a: get_associated_token_address(&pool_key, &pool.mint_a),
+        pool_token_b: get_associated_token_address(&pool_key, &pool.mint_b),
+        vault_a: pda::vault_address(&pool_key, &pool.mint_a).0,
+        vault_b: pda::vault_address(&pool_key, &pool.mint_b).0,
+        payer: *payer,
+        system_program: system_program::ID,
+        token_program: token::ID,
+        associated_token_program: associated_token::ID,
+    };
+
+    vec![
+        Instruction {
+            program_id: ID,
+            accounts: migrate_pool.to_account_metas(None),
+            data: instruction::MigratePool {}.data(),
+        },
+        Instruction {
+            program_id: ID,
+            accounts: migrate_vaults.to_account_metas(None),
+            data: instruction::MigrateVaults {}.data(),
+        },
+    ]
+}
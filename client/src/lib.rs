@@ -0,0 +1,13 @@
+// Rust client for the babyswap program.
+//
+// Everything here runs off-chain and only builds or reads data: PDA
+// derivations, instruction builders, `PoolInfo` decoding and swap and
+// liquidity quotes computed with the program's own curve math. Fetching
+// accounts and sending transactions is left to the caller's RPC client.
+
+pub mod accounts;
+pub mod instructions;
+pub mod pda;
+pub mod quote;
+
+pub use soondex::{CurveType, PoolInfo, ID};
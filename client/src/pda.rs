@@ -0,0 +1,58 @@
+// Program addresses, derived with the same seeds as the program's account
+// constraints.
+
+use anchor_lang::prelude::Pubkey;
+use soondex::ID;
+
+pub fn pool_address(mint_b: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"pool", mint_b.as_ref()], &ID)
+}
+
+pub fn vault_address(pool: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", pool.as_ref(), mint.as_ref()], &ID)
+}
+
+pub fn lp_mint_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"lp_mint", pool.as_ref()], &ID)
+}
+
+pub fn creator_fee_vault_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"creator_fees", pool.as_ref()], &ID)
+}
+
+pub fn config_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], &ID)
+}
+
+pub fn registry_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"registry"], &ID)
+}
+
+pub fn registry_entry_address(index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"pool_entry".as_ref(), &index.to_le_bytes()], &ID)
+}
+
+pub fn twamm_address(pool: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"twamm", pool.as_ref()], &ID)
+}
+
+pub fn twamm_vault_address(twamm: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"twamm_vault", twamm.as_ref(), mint.as_ref()], &ID)
+}
+
+pub fn allowlist_address(pool: &Pubkey, trader: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"allowlist", pool.as_ref(), trader.as_ref()], &ID)
+}
+
+pub fn lp_position_address(pool: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"lp_position", pool.as_ref(), owner.as_ref()], &ID)
+}
+
+// Metaplex metadata account of `mint`
+pub fn metadata_address(mint: &Pubkey) -> (Pubkey, u8) {
+    let metadata_program = anchor_spl::metadata::ID;
+    Pubkey::find_program_address(
+        &[b"metadata", metadata_program.as_ref(), mint.as_ref()],
+        &metadata_program,
+    )
+}
@@ -0,0 +1,141 @@
+// Off-chain quotes, priced with the same `PoolInfo` methods the handlers use.
+//
+// Reserves are the vault balances and `now` the cluster's unix timestamp, so
+// dynamic fees and amp ramps quote as they would execute. Pools with open
+// long-term orders settle them before every trade, which these quotes don't
+// model.
+
+use anchor_lang::Result;
+use soondex::PoolInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapQuote {
+    // Tokens the user receives
+    pub amount_out: u64,
+    // Swap fee in token A, including any referral and creator shares
+    pub fee: u64,
+    // Fee rate charged, in bps
+    pub fee_rate: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepositQuote {
+    // Tokens `add_liquidity` takes from the user
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub lp_amount: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WithdrawQuote {
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+// `buy`: `amount` of token A in, token B out
+pub fn quote_buy(pool: &PoolInfo, reserve_a: u64, reserve_b: u64, amount: u64, now: i64) -> Result<SwapQuote> {
+    let (amount_out, fee) = pool.quote_swap(reserve_a as u128, reserve_b as u128, amount as u128, true, now)?;
+    Ok(SwapQuote {
+        amount_out: amount_out as u64,
+        fee: fee as u64,
+        fee_rate: pool.current_fee(now),
+    })
+}
+
+// `sell`: `amount` of token B in, token A out
+pub fn quote_sell(pool: &PoolInfo, reserve_a: u64, reserve_b: u64, amount: u64, now: i64) -> Result<SwapQuote> {
+    let (amount_out, fee) = pool.quote_swap(reserve_b as u128, reserve_a as u128, amount as u128, false, now)?;
+    Ok(SwapQuote {
+        amount_out: amount_out as u64,
+        fee: fee as u64,
+        fee_rate: pool.current_fee(now),
+    })
+}
+
+// `add_liquidity(amount_a, amount_b)`. Once the pool holds liquidity only the
+// token B matching `amount_a` at the pool ratio is taken, and `amount_b` must
+// cover it.
+pub fn quote_add_liquidity(
+    pool: &PoolInfo,
+    reserve_a: u64,
+    reserve_b: u64,
+    amount_a: u64,
+    amount_b: u64,
+) -> Result<DepositQuote> {
+    let (amount_a, amount_b, lp_amount) =
+        pool.deposit_amounts(reserve_a as u128, reserve_b as u128, amount_a as u128, amount_b as u128)?;
+    Ok(DepositQuote {
+        amount_a,
+        amount_b,
+        lp_amount: lp_amount as u64,
+    })
+}
+
+// `remove_liquidity(lp_amount)`
+pub fn quote_remove_liquidity(pool: &PoolInfo, reserve_a: u64, reserve_b: u64, lp_amount: u64) -> Result<WithdrawQuote> {
+    let (amount_a, amount_b) = pool.withdraw_amounts(reserve_a as u128, reserve_b as u128, lp_amount as u128)?;
+    Ok(WithdrawQuote {
+        amount_a: amount_a as u64,
+        amount_b: amount_b as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::prelude::Pubkey;
+    use soondex::math::constant_product_output;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn pool(total_liquidity: u128) -> PoolInfo {
+        let mut pool: PoolInfo = bytemuck::Zeroable::zeroed();
+        let [key, mint_a, mint_b, lp_mint] = [(); 4].map(|_| Pubkey::new_unique());
+        pool.initialize(key, mint_a, mint_b, lp_mint, 30, NOW);
+        pool.total_liquidity = total_liquidity;
+        pool
+    }
+
+    #[test]
+    fn swap_quotes_match_the_curve() {
+        let pool = pool(1_000_000);
+        let quote = quote_buy(&pool, 1_000_000, 2_000_000, 10_000, NOW).unwrap();
+        // 30 bps off the input, then the constant product output
+        let expected = constant_product_output(1_000_000, 2_000_000, 10_000 - 30).unwrap();
+        assert_eq!((quote.amount_out as u128, quote.fee, quote.fee_rate), (expected, 30, 30));
+
+        let quote = quote_sell(&pool, 1_000_000, 2_000_000, 10_000, NOW).unwrap();
+        let (amount_out, fee) = pool.quote_swap(2_000_000, 1_000_000, 10_000, false, NOW).unwrap();
+        assert_eq!((quote.amount_out as u128, quote.fee as u128), (amount_out, fee));
+    }
+
+    #[test]
+    fn deposit_quotes_match_the_program() {
+        // First deposit, then one into a 1:2 pool with spare token B
+        for (pool, reserves, amounts) in [
+            (pool(0), (0, 0), (1_000, 5_000)),
+            (pool(1_000_000), (1_000_000, 2_000_000), (1_000, 5_000)),
+        ] {
+            let quote = quote_add_liquidity(&pool, reserves.0, reserves.1, amounts.0, amounts.1).unwrap();
+            let (amount_a, amount_b, lp_amount) = pool
+                .deposit_amounts(reserves.0 as u128, reserves.1 as u128, amounts.0 as u128, amounts.1 as u128)
+                .unwrap();
+            assert_eq!((quote.amount_a, quote.amount_b, quote.lp_amount as u128), (amount_a, amount_b, lp_amount));
+        }
+
+        let pool = pool(1_000_000);
+        let quote = quote_add_liquidity(&pool, 1_000_000, 2_000_000, 1_000, 5_000).unwrap();
+        assert_eq!(quote, DepositQuote { amount_a: 1_000, amount_b: 2_000, lp_amount: 1_000 });
+        assert!(quote_add_liquidity(&pool, 1_000_000, 2_000_000, 1_000, 1_999).is_err());
+    }
+
+    #[test]
+    fn withdraw_quotes_match_the_program() {
+        let pool = pool(1_000_000);
+        let quote = quote_remove_liquidity(&pool, 1_000_000, 2_000_000, 1_000).unwrap();
+        let (amount_a, amount_b) = pool.withdraw_amounts(1_000_000, 2_000_000, 1_000).unwrap();
+        assert_eq!((quote.amount_a as u128, quote.amount_b as u128), (amount_a, amount_b));
+        assert_eq!(quote, WithdrawQuote { amount_a: 1_000, amount_b: 2_000 });
+        assert!(quote_remove_liquidity(&self::pool(0), 1_000_000, 2_000_000, 1_000).is_err());
+    }
+}
@@ -0,0 +1,1007 @@
+// Concentrated liquidity pools.
+//
+// These live next to the full-range `PoolInfo` pools: LPs pick a tick range for
+// their liquidity, and swaps step through the initialized ticks of the pool's
+// tick arrays, which are passed in `remaining_accounts` in swap order. Like the
+// full-range pools, reserves sit in token accounts owned by the pool at
+// `[b"vault", pool, mint]`.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::{transfer, Mint, Token, TokenAccount, Transfer};
+
+use crate::tick_math::*;
+use crate::ErrorCode;
+
+// Ticks per tick array
+pub const TICK_ARRAY_SIZE: i32 = 64;
+pub const MAX_TICK_SPACING: u16 = 256;
+
+#[account]
+pub struct ClPool {
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub tick_spacing: u16,
+    pub fees: u64,
+    // Current sqrt(price) in Q64.64 and the tick it falls in
+    pub sqrt_price: u128,
+    pub tick_current: i32,
+    // Liquidity of the positions in range at the current tick
+    pub liquidity: u128,
+    // Fees earned per unit of liquidity over the pool's lifetime, Q64.64
+    pub fee_growth_global_a: u128,
+    pub fee_growth_global_b: u128,
+    // Reserve vaults, token accounts owned by the pool at `[b"vault", pool, mint]`
+    pub vault_a: Pubkey,
+    pub vault_b: Pubkey,
+    // Bumps of the pool PDA and vaults, saved at creation
+    pub bump: u8,
+    pub vault_a_bump: u8,
+    pub vault_b_bump: u8,
+}
+
+impl ClPool {
+    pub fn ticks_in_array(&self) -> i32 {
+        TICK_ARRAY_SIZE * self.tick_spacing as i32
+    }
+
+    pub fn validate_tick_range(&self, tick_lower: i32, tick_upper: i32) -> Result<()> {
+        let spacing = self.tick_spacing as i32;
+        require!(tick_lower < tick_upper, ErrorCode::InvalidTickRange);
+        require!(
+            tick_lower >= MIN_TICK && tick_upper <= MAX_TICK,
+            ErrorCode::InvalidTickRange
+        );
+        require!(
+            tick_lower % spacing == 0 && tick_upper % spacing == 0,
+            ErrorCode::InvalidTickRange
+        );
+        Ok(())
+    }
+
+    // Token amounts backing `liquidity` in the given range at the current price
+    pub fn amounts_for_liquidity(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        round_up: bool,
+    ) -> Result<(u64, u64)> {
+        let sqrt_lower = sqrt_price_at_tick(tick_lower).ok_or(ErrorCode::InvalidTickIndex)?;
+        let sqrt_upper = sqrt_price_at_tick(tick_upper).ok_or(ErrorCode::InvalidTickIndex)?;
+
+        let (amount_a, amount_b) = if self.tick_current < tick_lower {
+            let amount_a = amount_a_delta(sqrt_lower, sqrt_upper, liquidity, round_up);
+            (amount_a, Some(0))
+        } else if self.tick_current < tick_upper {
+            let amount_a = amount_a_delta(self.sqrt_price, sqrt_upper, liquidity, round_up);
+            let amount_b = amount_b_delta(sqrt_lower, self.sqrt_price, liquidity, round_up);
+            (amount_a, amount_b)
+        } else {
+            let amount_b = amount_b_delta(sqrt_lower, sqrt_upper, liquidity, round_up);
+            (Some(0), amount_b)
+        };
+
+        let amount_a = amount_a
+            .and_then(|amount| u64::try_from(amount).ok())
+            .ok_or(ErrorCode::Overflow)?;
+        let amount_b = amount_b
+            .and_then(|amount| u64::try_from(amount).ok())
+            .ok_or(ErrorCode::Overflow)?;
+
+        Ok((amount_a, amount_b))
+    }
+}
+
+#[zero_copy]
+#[derive(Default)]
+pub struct Tick {
+    // Liquidity added (lower tick) or removed (upper tick) when crossing upwards
+    pub liquidity_net: i128,
+    // Total liquidity referencing this tick, zero means uninitialized
+    pub liquidity_gross: u128,
+    // Fee growth on the other side of this tick from the current tick
+    pub fee_growth_outside_a: u128,
+    pub fee_growth_outside_b: u128,
+}
+
+impl Tick {
+    pub fn is_initialized(&self) -> bool {
+        self.liquidity_gross > 0
+    }
+}
+
+#[account(zero_copy)]
+pub struct TickArray {
+    pub pool: Pubkey,
+    pub start_tick_index: i32,
+    pub _padding: [u8; 12],
+    pub ticks: [Tick; TICK_ARRAY_SIZE as usize],
+}
+
+impl TickArray {
+    fn offset(&self, tick_index: i32, tick_spacing: i32) -> Result<usize> {
+        let distance = tick_index - self.start_tick_index;
+        require!(distance % tick_spacing == 0, ErrorCode::InvalidTickIndex);
+
+        let offset = distance / tick_spacing;
+        require!(
+            (0..TICK_ARRAY_SIZE).contains(&offset),
+            ErrorCode::InvalidTickArray
+        );
+        Ok(offset as usize)
+    }
+
+    pub fn tick(&self, tick_index: i32, tick_spacing: i32) -> Result<Tick> {
+        let offset = self.offset(tick_index, tick_spacing)?;
+        Ok(self.ticks[offset])
+    }
+
+    // Apply a liquidity change to one end of a position. Returns the tick as it
+    // was right after the change, before it is cleared for having no liquidity left.
+    pub fn update_tick(
+        &mut self,
+        pool: &ClPool,
+        tick_index: i32,
+        liquidity_delta: i128,
+        is_upper: bool,
+    ) -> Result<Tick> {
+        let offset = self.offset(tick_index, pool.tick_spacing as i32)?;
+        let tick = &mut self.ticks[offset];
+
+        let gross_before = tick.liquidity_gross;
+        let gross_after = add_liquidity_delta(gross_before, liquidity_delta)?;
+
+        // By convention all fee growth so far happened below a freshly initialized tick
+        if gross_before == 0 && tick_index <= pool.tick_current {
+            tick.fee_growth_outside_a = pool.fee_growth_global_a;
+            tick.fee_growth_outside_b = pool.fee_growth_global_b;
+        }
+
+        tick.liquidity_gross = gross_after;
+        tick.liquidity_net = if is_upper {
+            tick.liquidity_net.checked_sub(liquidity_delta)
+        } else {
+            tick.liquidity_net.checked_add(liquidity_delta)
+        }
+        .ok_or(ErrorCode::LiquidityOverflow)?;
+
+        let updated = *tick;
+        if gross_after == 0 {
+            *tick = Tick::default();
+        }
+        Ok(updated)
+    }
+
+    // Next initialized tick to cross from `tick_current` in the swap direction,
+    // or `None` with the array boundary when this array has none left
+    pub fn next_initialized_tick(
+        &self,
+        tick_current: i32,
+        tick_spacing: i32,
+        a_to_b: bool,
+    ) -> Result<(Option<usize>, i32)> {
+        let offset = (tick_current - self.start_tick_index).div_euclid(tick_spacing);
+
+        if a_to_b {
+            // Price moves down: ticks at or below the current tick
+            require!(
+                (0..TICK_ARRAY_SIZE).contains(&offset),
+                ErrorCode::InvalidTickArray
+            );
+            for i in (0..=offset as usize).rev() {
+                if self.ticks[i].is_initialized() {
+                    return Ok((Some(i), self.tick_index_at(i, tick_spacing)));
+                }
+            }
+            Ok((None, self.start_tick_index))
+        } else {
+            // Price moves up: ticks strictly above the current tick
+            let first = offset + 1;
+            require!(
+                (0..=TICK_ARRAY_SIZE).contains(&first),
+                ErrorCode::InvalidTickArray
+            );
+            for i in first as usize..TICK_ARRAY_SIZE as usize {
+                if self.ticks[i].is_initialized() {
+                    return Ok((Some(i), self.tick_index_at(i, tick_spacing)));
+                }
+            }
+            Ok((None, self.start_tick_index + TICK_ARRAY_SIZE * tick_spacing))
+        }
+    }
+
+    fn tick_index_at(&self, offset: usize, tick_spacing: i32) -> i32 {
+        self.start_tick_index + offset as i32 * tick_spacing
+    }
+}
+
+#[account]
+pub struct Position {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub liquidity: u128,
+    // Fee growth inside the range at the last update, Q64.64
+    pub fee_growth_inside_a: u128,
+    pub fee_growth_inside_b: u128,
+    pub fees_owed_a: u64,
+    pub fees_owed_b: u64,
+}
+
+impl Position {
+    // Accrue fees earned since the last update, then apply the liquidity change
+    pub fn update(
+        &mut self,
+        fee_growth_inside: (u128, u128),
+        liquidity_delta: i128,
+    ) -> Result<()> {
+        let delta_a = fee_growth_inside.0.wrapping_sub(self.fee_growth_inside_a);
+        let delta_b = fee_growth_inside.1.wrapping_sub(self.fee_growth_inside_b);
+        let earned_a = fees_owed(self.liquidity, delta_a).ok_or(ErrorCode::Overflow)?;
+        let earned_b = fees_owed(self.liquidity, delta_b).ok_or(ErrorCode::Overflow)?;
+
+        self.fees_owed_a = self.fees_owed_a.checked_add(earned_a).ok_or(ErrorCode::Overflow)?;
+        self.fees_owed_b = self.fees_owed_b.checked_add(earned_b).ok_or(ErrorCode::Overflow)?;
+        self.fee_growth_inside_a = fee_growth_inside.0;
+        self.fee_growth_inside_b = fee_growth_inside.1;
+        self.liquidity = add_liquidity_delta(self.liquidity, liquidity_delta)?;
+
+        Ok(())
+    }
+}
+
+fn add_liquidity_delta(liquidity: u128, delta: i128) -> Result<u128> {
+    let result = if delta >= 0 {
+        liquidity.checked_add(delta as u128)
+    } else {
+        liquidity.checked_sub(delta.unsigned_abs())
+    };
+    result.ok_or(ErrorCode::LiquidityOverflow.into())
+}
+
+// Fee growth between a position's ticks, which wraps like the global fee growth does
+fn fee_growth_inside(pool: &ClPool, position: &Position, lower: &Tick, upper: &Tick) -> (u128, u128) {
+    let global_a = pool.fee_growth_global_a;
+    let global_b = pool.fee_growth_global_b;
+
+    let (below_a, below_b) = if pool.tick_current >= position.tick_lower {
+        (lower.fee_growth_outside_a, lower.fee_growth_outside_b)
+    } else {
+        (
+            global_a.wrapping_sub(lower.fee_growth_outside_a),
+            global_b.wrapping_sub(lower.fee_growth_outside_b),
+        )
+    };
+
+    let (above_a, above_b) = if pool.tick_current < position.tick_upper {
+        (upper.fee_growth_outside_a, upper.fee_growth_outside_b)
+    } else {
+        (
+            global_a.wrapping_sub(upper.fee_growth_outside_a),
+            global_b.wrapping_sub(upper.fee_growth_outside_b),
+        )
+    };
+
+    (
+        global_a.wrapping_sub(below_a).wrapping_sub(above_a),
+        global_b.wrapping_sub(below_b).wrapping_sub(above_b),
+    )
+}
+
+// Update both ends of a position, which may share one tick array
+fn update_position_ticks(
+    pool: &ClPool,
+    pool_key: Pubkey,
+    position: &Position,
+    tick_array_lower: &AccountLoader<TickArray>,
+    tick_array_upper: &AccountLoader<TickArray>,
+    liquidity_delta: i128,
+) -> Result<(Tick, Tick)> {
+    if tick_array_lower.key() == tick_array_upper.key() {
+        let mut array = tick_array_lower.load_mut()?;
+        require_keys_eq!(array.pool, pool_key, ErrorCode::InvalidTickArray);
+
+        let lower = array.update_tick(pool, position.tick_lower, liquidity_delta, false)?;
+        let upper = array.update_tick(pool, position.tick_upper, liquidity_delta, true)?;
+        Ok((lower, upper))
+    } else {
+        let mut lower_array = tick_array_lower.load_mut()?;
+        let mut upper_array = tick_array_upper.load_mut()?;
+        require_keys_eq!(lower_array.pool, pool_key, ErrorCode::InvalidTickArray);
+        require_keys_eq!(upper_array.pool, pool_key, ErrorCode::InvalidTickArray);
+
+        let lower = lower_array.update_tick(pool, position.tick_lower, liquidity_delta, false)?;
+        let upper = upper_array.update_tick(pool, position.tick_upper, liquidity_delta, true)?;
+        Ok((lower, upper))
+    }
+}
+
+// Shared by `increase_liquidity` and `decrease_liquidity`: updates ticks,
+// position and in-range liquidity and returns the token amounts involved
+fn modify_liquidity(accounts: &mut ModifyLiquidity, liquidity_delta: i128) -> Result<(u64, u64)> {
+    let pool_key = accounts.pool.key();
+    let pool = &mut accounts.pool;
+    let position = &mut accounts.position;
+
+    let (lower, upper) = update_position_ticks(
+        pool,
+        pool_key,
+        position,
+        &accounts.tick_array_lower,
+        &accounts.tick_array_upper,
+        liquidity_delta,
+    )?;
+
+    let inside = fee_growth_inside(pool, position, &lower, &upper);
+    position.update(inside, liquidity_delta)?;
+
+    if (position.tick_lower..position.tick_upper).contains(&pool.tick_current) {
+        pool.liquidity = add_liquidity_delta(pool.liquidity, liquidity_delta)?;
+    }
+
+    pool.amounts_for_liquidity(
+        position.tick_lower,
+        position.tick_upper,
+        liquidity_delta.unsigned_abs(),
+        liquidity_delta > 0,
+    )
+}
+
+pub fn initialize_cl_pool(
+    ctx: Context<InitializeClPool>,
+    tick_spacing: u16,
+    fee: u64,
+    sqrt_price: u128,
+) -> Result<()> {
+    // Fee Should Be 3% Max
+    if fee.gt(&300) {
+        return Err(ErrorCode::FeeTooHigh.into());
+    }
+    if tick_spacing == 0 || tick_spacing > MAX_TICK_SPACING {
+        return Err(ErrorCode::InvalidTickSpacing.into());
+    }
+    let tick_current = tick_at_sqrt_price(sqrt_price).ok_or(ErrorCode::InvalidSqrtPrice)?;
+
+    let pool = &mut ctx.accounts.pool;
+    pool.mint_a = ctx.accounts.mint_a.key();
+    pool.mint_b = ctx.accounts.mint_b.key();
+    pool.tick_spacing = tick_spacing;
+    pool.fees = fee;
+    pool.sqrt_price = sqrt_price;
+    pool.tick_current = tick_current;
+    pool.liquidity = 0;
+    pool.fee_growth_global_a = 0;
+    pool.fee_growth_global_b = 0;
+    pool.vault_a = ctx.accounts.vault_a.key();
+    pool.vault_b = ctx.accounts.vault_b.key();
+    pool.bump = ctx.bumps.pool;
+    pool.vault_a_bump = ctx.bumps.vault_a;
+    pool.vault_b_bump = ctx.bumps.vault_b;
+
+    msg!(
+        "Concentrated Pool Initialized mint_a: {:?}, mint_b: {:?}, fee: {}, tick spacing: {}, tick: {}",
+        pool.mint_a, pool.mint_b, fee, tick_spacing, tick_current
+    );
+
+    Ok(())
+}
+
+pub fn initialize_tick_array(ctx: Context<InitializeTickArray>, start_tick_index: i32) -> Result<()> {
+    let ticks_in_array = ctx.accounts.pool.ticks_in_array();
+    require!(
+        start_tick_index % ticks_in_array == 0,
+        ErrorCode::InvalidTickArray
+    );
+    require!(
+        start_tick_index <= MAX_TICK && start_tick_index + ticks_in_array > MIN_TICK,
+        ErrorCode::InvalidTickArray
+    );
+
+    let mut tick_array = ctx.accounts.tick_array.load_init()?;
+    tick_array.pool = ctx.accounts.pool.key();
+    tick_array.start_tick_index = start_tick_index;
+
+    Ok(())
+}
+
+pub fn open_position(ctx: Context<OpenPosition>, tick_lower: i32, tick_upper: i32) -> Result<()> {
+    ctx.accounts.pool.validate_tick_range(tick_lower, tick_upper)?;
+
+    let position = &mut ctx.accounts.position;
+    position.pool = ctx.accounts.pool.key();
+    position.owner = ctx.accounts.owner.key();
+    position.tick_lower = tick_lower;
+    position.tick_upper = tick_upper;
+    position.liquidity = 0;
+    position.fee_growth_inside_a = 0;
+    position.fee_growth_inside_b = 0;
+    position.fees_owed_a = 0;
+    position.fees_owed_b = 0;
+
+    msg!("Opened Position: ticks {} to {}", tick_lower, tick_upper);
+
+    Ok(())
+}
+
+pub fn increase_liquidity(
+    ctx: Context<ModifyLiquidity>,
+    liquidity: u128,
+    token_max_a: u64,
+    token_max_b: u64,
+) -> Result<()> {
+    require!(liquidity > 0, ErrorCode::InvalidAmount);
+    let liquidity_delta = i128::try_from(liquidity).map_err(|_| ErrorCode::LiquidityOverflow)?;
+
+    let (amount_a, amount_b) = modify_liquidity(ctx.accounts, liquidity_delta)?;
+    require!(
+        amount_a <= token_max_a && amount_b <= token_max_b,
+        ErrorCode::TokenMaxExceeded
+    );
+
+    let accounts = &ctx.accounts;
+    for (from, to, amount) in [
+        (&accounts.user_token_a, &accounts.vault_a, amount_a),
+        (&accounts.user_token_b, &accounts.vault_b, amount_b),
+    ] {
+        if amount > 0 {
+            transfer(
+                CpiContext::new(
+                    accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: from.to_account_info(),
+                        to: to.to_account_info(),
+                        authority: accounts.owner.to_account_info(),
+                    },
+                ),
+                amount,
+            )?;
+        }
+    }
+
+    msg!(
+        "Increase Liquidity: {} liquidity for {} token A and {} token B",
+        liquidity, amount_a, amount_b
+    );
+
+    Ok(())
+}
+
+pub fn decrease_liquidity(
+    ctx: Context<ModifyLiquidity>,
+    liquidity: u128,
+    token_min_a: u64,
+    token_min_b: u64,
+) -> Result<()> {
+    require!(liquidity > 0, ErrorCode::InvalidAmount);
+    require!(
+        liquidity <= ctx.accounts.position.liquidity,
+        ErrorCode::InsufficientLiquidity
+    );
+    let liquidity_delta = i128::try_from(liquidity)
+        .map(|delta| -delta)
+        .map_err(|_| ErrorCode::LiquidityOverflow)?;
+
+    let (amount_a, amount_b) = modify_liquidity(ctx.accounts, liquidity_delta)?;
+    require!(
+        amount_a >= token_min_a && amount_b >= token_min_b,
+        ErrorCode::InsufficientOutputAmount
+    );
+
+    let accounts = &ctx.accounts;
+    let spacing_bytes = accounts.pool.tick_spacing.to_le_bytes();
+    let seeds: &[&[u8]] = &[
+        b"cl_pool",
+        accounts.pool.mint_a.as_ref(),
+        accounts.pool.mint_b.as_ref(),
+        &spacing_bytes,
+        &[accounts.pool.bump],
+    ];
+    let signer = &[seeds];
+
+    for (from, to, amount) in [
+        (&accounts.vault_a, &accounts.user_token_a, amount_a),
+        (&accounts.vault_b, &accounts.user_token_b, amount_b),
+    ] {
+        if amount > 0 {
+            transfer(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: from.to_account_info(),
+                        to: to.to_account_info(),
+                        authority: accounts.pool.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount,
+            )?;
+        }
+    }
+
+    msg!(
+        "Decrease Liquidity: {} liquidity for {} token A and {} token B",
+        liquidity, amount_a, amount_b
+    );
+
+    Ok(())
+}
+
+pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
+    let pool_key = ctx.accounts.pool.key();
+    let pool = &ctx.accounts.pool;
+    let position = &mut ctx.accounts.position;
+    let spacing = pool.tick_spacing as i32;
+
+    let lower_array = ctx.accounts.tick_array_lower.load()?;
+    let upper_array = ctx.accounts.tick_array_upper.load()?;
+    require_keys_eq!(lower_array.pool, pool_key, ErrorCode::InvalidTickArray);
+    require_keys_eq!(upper_array.pool, pool_key, ErrorCode::InvalidTickArray);
+    let lower = lower_array.tick(position.tick_lower, spacing)?;
+    let upper = upper_array.tick(position.tick_upper, spacing)?;
+    drop(lower_array);
+    drop(upper_array);
+
+    let inside = fee_growth_inside(pool, position, &lower, &upper);
+    position.update(inside, 0)?;
+
+    let amount_a = position.fees_owed_a;
+    let amount_b = position.fees_owed_b;
+    position.fees_owed_a = 0;
+    position.fees_owed_b = 0;
+
+    let spacing_bytes = pool.tick_spacing.to_le_bytes();
+    let seeds: &[&[u8]] = &[
+        b"cl_pool",
+        pool.mint_a.as_ref(),
+        pool.mint_b.as_ref(),
+        &spacing_bytes,
+        &[pool.bump],
+    ];
+    let signer = &[seeds];
+
+    let accounts = &ctx.accounts;
+    for (from, to, amount) in [
+        (&accounts.vault_a, &accounts.user_token_a, amount_a),
+        (&accounts.vault_b, &accounts.user_token_b, amount_b),
+    ] {
+        if amount > 0 {
+            transfer(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: from.to_account_info(),
+                        to: to.to_account_info(),
+                        authority: accounts.pool.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount,
+            )?;
+        }
+    }
+
+    msg!("Collect Fees: {} token A and {} token B", amount_a, amount_b);
+
+    Ok(())
+}
+
+pub fn cl_swap<'info>(
+    ctx: Context<'_, '_, 'info, 'info, ClSwap<'info>>,
+    amount: u64,
+    min_amount_out: u64,
+    a_to_b: bool,
+    sqrt_price_limit: u128,
+) -> Result<()> {
+    require!(amount > 0, ErrorCode::InvalidSwapAmount);
+
+    let pool_key = ctx.accounts.pool.key();
+    let pool = &mut ctx.accounts.pool;
+    let tick_spacing = pool.tick_spacing as i32;
+
+    // Zero means no price limit
+    let sqrt_price_limit = match (sqrt_price_limit, a_to_b) {
+        (0, true) => MIN_SQRT_PRICE,
+        (0, false) => MAX_SQRT_PRICE,
+        (limit, _) => limit,
+    };
+    let limit_valid = if a_to_b {
+        sqrt_price_limit >= MIN_SQRT_PRICE && sqrt_price_limit < pool.sqrt_price
+    } else {
+        sqrt_price_limit <= MAX_SQRT_PRICE && sqrt_price_limit > pool.sqrt_price
+    };
+    require!(limit_valid, ErrorCode::InvalidSqrtPriceLimit);
+
+    let mut amount_remaining = amount as u128;
+    let mut amount_out: u128 = 0;
+    let mut sqrt_price = pool.sqrt_price;
+    let mut tick_current = pool.tick_current;
+    let mut liquidity = pool.liquidity;
+    let mut fee_growth_global_a = pool.fee_growth_global_a;
+    let mut fee_growth_global_b = pool.fee_growth_global_b;
+    let mut array_index = 0;
+
+    while amount_remaining > 0 && sqrt_price != sqrt_price_limit {
+        // Stop at the end of the last tick array supplied by the caller
+        let Some(account_info) = ctx.remaining_accounts.get(array_index) else {
+            break;
+        };
+        let loader = AccountLoader::<TickArray>::try_from(account_info)?;
+        let mut array = loader.load_mut()?;
+        require_keys_eq!(array.pool, pool_key, ErrorCode::InvalidTickArray);
+
+        let (next_offset, next_tick) =
+            array.next_initialized_tick(tick_current, tick_spacing, a_to_b)?;
+        let next_tick = next_tick.clamp(MIN_TICK, MAX_TICK);
+        let sqrt_price_next_tick = sqrt_price_at_tick(next_tick).ok_or(ErrorCode::InvalidTickIndex)?;
+        let sqrt_price_target = if a_to_b {
+            sqrt_price_next_tick.max(sqrt_price_limit)
+        } else {
+            sqrt_price_next_tick.min(sqrt_price_limit)
+        };
+
+        let step = compute_swap_step(
+            sqrt_price,
+            sqrt_price_target,
+            liquidity,
+            amount_remaining,
+            pool.fees,
+            a_to_b,
+        )
+        .ok_or(ErrorCode::Overflow)?;
+
+        amount_remaining = step
+            .amount_in
+            .checked_add(step.fee_amount)
+            .and_then(|spent| amount_remaining.checked_sub(spent))
+            .ok_or(ErrorCode::Overflow)?;
+        amount_out = amount_out
+            .checked_add(step.amount_out)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let growth = fee_growth_delta(step.fee_amount, liquidity).ok_or(ErrorCode::Overflow)?;
+        if a_to_b {
+            fee_growth_global_a = fee_growth_global_a.wrapping_add(growth);
+        } else {
+            fee_growth_global_b = fee_growth_global_b.wrapping_add(growth);
+        }
+
+        if step.sqrt_price_next == sqrt_price_next_tick {
+            match next_offset {
+                Some(offset) => {
+                    // Cross an initialized tick: flip its outside fee growth and
+                    // pick up or drop the liquidity of the positions it bounds
+                    let tick = &mut array.ticks[offset];
+                    tick.fee_growth_outside_a = fee_growth_global_a.wrapping_sub(tick.fee_growth_outside_a);
+                    tick.fee_growth_outside_b = fee_growth_global_b.wrapping_sub(tick.fee_growth_outside_b);
+
+                    let liquidity_net = if a_to_b { -tick.liquidity_net } else { tick.liquidity_net };
+                    liquidity = add_liquidity_delta(liquidity, liquidity_net)?;
+                    tick_current = if a_to_b { next_tick - 1 } else { next_tick };
+
+                    if a_to_b && next_tick == array.start_tick_index {
+                        array_index += 1;
+                    }
+                }
+                None => {
+                    // Reached the end of this tick array, continue in the next one
+                    tick_current = next_tick - 1;
+                    array_index += 1;
+                }
+            }
+            sqrt_price = step.sqrt_price_next;
+        } else if step.sqrt_price_next != sqrt_price {
+            sqrt_price = step.sqrt_price_next;
+            tick_current = tick_at_sqrt_price(sqrt_price).ok_or(ErrorCode::InvalidSqrtPrice)?;
+        }
+    }
+
+    pool.sqrt_price = sqrt_price;
+    pool.tick_current = tick_current;
+    pool.liquidity = liquidity;
+    pool.fee_growth_global_a = fee_growth_global_a;
+    pool.fee_growth_global_b = fee_growth_global_b;
+
+    let amount_in = amount - amount_remaining as u64;
+    let amount_out = u64::try_from(amount_out).map_err(|_| ErrorCode::Overflow)?;
+    require!(amount_in > 0, ErrorCode::InsufficientLiquidity);
+    require!(amount_out >= min_amount_out, ErrorCode::InsufficientOutputAmount);
+
+    let accounts = &ctx.accounts;
+    let (user_in, vault_in, vault_out, user_out) = if a_to_b {
+        (&accounts.user_token_a, &accounts.vault_a, &accounts.vault_b, &accounts.user_token_b)
+    } else {
+        (&accounts.user_token_b, &accounts.vault_b, &accounts.vault_a, &accounts.user_token_a)
+    };
+
+    transfer(
+        CpiContext::new(
+            accounts.token_program.to_account_info(),
+            Transfer {
+                from: user_in.to_account_info(),
+                to: vault_in.to_account_info(),
+                authority: accounts.user.to_account_info(),
+            },
+        ),
+        amount_in,
+    )?;
+
+    let spacing_bytes = accounts.pool.tick_spacing.to_le_bytes();
+    let seeds: &[&[u8]] = &[
+        b"cl_pool",
+        accounts.pool.mint_a.as_ref(),
+        accounts.pool.mint_b.as_ref(),
+        &spacing_bytes,
+        &[accounts.pool.bump],
+    ];
+    transfer(
+        CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            Transfer {
+                from: vault_out.to_account_info(),
+                to: user_out.to_account_info(),
+                authority: accounts.pool.to_account_info(),
+            },
+            &[seeds],
+        ),
+        amount_out,
+    )?;
+
+    msg!(
+        "Concentrated Swap: {} tokens in for {} tokens out, tick {}",
+        amount_in, amount_out, tick_current
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(tick_spacing: u16)]
+pub struct InitializeClPool<'info> {
+    #[account(
+        init,
+        seeds = [
+            b"cl_pool",
+            mint_a.key().as_ref(),
+            mint_b.key().as_ref(),
+            &tick_spacing.to_le_bytes()
+        ],
+        bump,
+        payer = user,
+        space = 8 + std::mem::size_of::<ClPool>()
+    )]
+    pub pool: Box<Account<'info, ClPool>>,
+
+    // Mints are sorted so each pair and tick spacing has exactly one pool
+    #[account(constraint = mint_a.key() < mint_b.key() @ ErrorCode::InvalidTokenOrder)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            mint_a.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_a,
+        token::authority = pool,
+    )]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            mint_b.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_b,
+        token::authority = pool,
+    )]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>
+}
+
+#[derive(Accounts)]
+#[instruction(start_tick_index: i32)]
+pub struct InitializeTickArray<'info> {
+    pub pool: Box<Account<'info, ClPool>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"tick_array",
+            pool.key().as_ref(),
+            &start_tick_index.to_le_bytes()
+        ],
+        bump,
+        payer = user,
+        space = 8 + std::mem::size_of::<TickArray>()
+    )]
+    pub tick_array: AccountLoader<'info, TickArray>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(tick_lower: i32, tick_upper: i32)]
+pub struct OpenPosition<'info> {
+    pub pool: Box<Account<'info, ClPool>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"position",
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            &tick_lower.to_le_bytes(),
+            &tick_upper.to_le_bytes()
+        ],
+        bump,
+        payer = owner,
+        space = 8 + std::mem::size_of::<Position>()
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ModifyLiquidity<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"cl_pool",
+            pool.mint_a.as_ref(),
+            pool.mint_b.as_ref(),
+            &pool.tick_spacing.to_le_bytes()
+        ],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, ClPool>>,
+
+    #[account(
+        mut,
+        has_one = pool,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    #[account(mut)]
+    pub tick_array_lower: AccountLoader<'info, TickArray>,
+    #[account(mut)]
+    pub tick_array_upper: AccountLoader<'info, TickArray>,
+
+    #[account(address = pool.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(address = pool.mint_b @ ErrorCode::InvalidPoolTokenB)]
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(mut, address = pool.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_token_a.owner == owner.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = user_token_a.mint == pool.mint_a @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub user_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = user_token_b.owner == owner.key() @ ErrorCode::InvalidUserTokenB,
+        constraint = user_token_b.mint == pool.mint_b @ ErrorCode::InvalidUserTokenBMint
+    )]
+    pub user_token_b: Box<Account<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFees<'info> {
+    #[account(
+        seeds = [
+            b"cl_pool",
+            pool.mint_a.as_ref(),
+            pool.mint_b.as_ref(),
+            &pool.tick_spacing.to_le_bytes()
+        ],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, ClPool>>,
+
+    #[account(
+        mut,
+        has_one = pool,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub position: Box<Account<'info, Position>>,
+
+    pub tick_array_lower: AccountLoader<'info, TickArray>,
+    pub tick_array_upper: AccountLoader<'info, TickArray>,
+
+    #[account(address = pool.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(address = pool.mint_b @ ErrorCode::InvalidPoolTokenB)]
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(mut, address = pool.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_token_a.owner == owner.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = user_token_a.mint == pool.mint_a @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub user_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = user_token_b.owner == owner.key() @ ErrorCode::InvalidUserTokenB,
+        constraint = user_token_b.mint == pool.mint_b @ ErrorCode::InvalidUserTokenBMint
+    )]
+    pub user_token_b: Box<Account<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClSwap<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"cl_pool",
+            pool.mint_a.as_ref(),
+            pool.mint_b.as_ref(),
+            &pool.tick_spacing.to_le_bytes()
+        ],
+        bump = pool.bump,
+    )]
+    pub pool: Box<Account<'info, ClPool>>,
+
+    #[account(address = pool.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(address = pool.mint_b @ ErrorCode::InvalidPoolTokenB)]
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(mut, address = pool.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_token_a.owner == user.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = user_token_a.mint == pool.mint_a @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub user_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = user_token_b.owner == user.key() @ ErrorCode::InvalidUserTokenB,
+        constraint = user_token_b.mint == pool.mint_b @ ErrorCode::InvalidUserTokenBMint
+    )]
+    pub user_token_b: Box<Account<'info, TokenAccount>>,
+
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
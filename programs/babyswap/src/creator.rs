@@ -0,0 +1,136 @@
+// Pool creator fees.
+//
+// The creator of a pool can take a share of every `buy` and `sell` fee, up to
+// the config's `max_creator_fee_bps`, which also caps fees set before it was
+// lowered. Swap fees are always charged in token A, so the share is moved into
+// a single vault at `[b"creator_fees", pool]` as each swap executes, and the
+// creator sweeps it with `claim_creator_fees`.
+//
+// Keeper-cranked swaps (limit order fills, DCA cycles and TWAMM settlements)
+// pay no creator share: their whole fee stays in the pool for LPs.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{transfer, Mint, Token, TokenAccount, Transfer},
+};
+
+use crate::{Config, ErrorCode, PoolInfo};
+
+pub fn set_creator_fee(ctx: Context<SetCreatorFee>, creator_fee_bps: u16) -> Result<()> {
+    if creator_fee_bps as u64 > ctx.accounts.config.max_creator_fee_bps {
+        return Err(ErrorCode::CreatorFeeTooHigh.into());
+    }
+
+    let mut pool = ctx.accounts.pool.load_mut()?;
+    pool.creator_fee_bps = creator_fee_bps;
+    pool.creator_fee_vault_bump = ctx.bumps.creator_fee_vault;
+
+    msg!("Pool {:?} creator fee set to {} bps of the swap fee", pool.pool, creator_fee_bps);
+
+    Ok(())
+}
+
+pub fn claim_creator_fees(ctx: Context<ClaimCreatorFees>) -> Result<()> {
+    let amount = ctx.accounts.creator_fee_vault.amount;
+    require!(amount > 0, ErrorCode::InvalidAmount);
+
+    let (pool_key, mint_b, bump) = {
+        let pool = ctx.accounts.pool.load()?;
+        (pool.pool, pool.mint_b, pool.bump)
+    };
+    let seeds: &[&[u8]] = &[b"pool", mint_b.as_ref(), &[bump]];
+
+    transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.creator_fee_vault.to_account_info(),
+                to: ctx.accounts.creator_token_account.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
+            },
+            &[seeds],
+        ),
+        amount,
+    )?;
+
+    msg!("Creator fees claimed from pool {:?}: {} tokens", pool_key, amount);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetCreatorFee<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        seeds = [
+            b"creator_fees",
+            pool.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_a,
+        token::authority = pool,
+    )]
+    pub creator_fee_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [b"config"], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(mut, address = pool.load()?.creator @ ErrorCode::Unauthorized)]
+    pub creator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimCreatorFees<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"creator_fees",
+            pool.key().as_ref()
+        ],
+        bump = pool.load()?.creator_fee_vault_bump,
+    )]
+    pub creator_fee_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        associated_token::mint = mint_a,
+        associated_token::authority = creator,
+    )]
+    pub creator_token_account: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, address = pool.load()?.creator @ ErrorCode::Unauthorized)]
+    pub creator: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
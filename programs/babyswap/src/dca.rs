@@ -0,0 +1,476 @@
+// Dollar-cost-averaging schedules.
+//
+// A DCA escrows its whole input up front and spends a fixed amount per cycle.
+// Anyone can crank `execute_dca` once a cycle is due; the swap must return at
+// least the pool's TWAP since the previous cycle, less the owner's slippage
+// allowance, so a crank can't be sandwiched into a bad fill. The keeper gets
+// the same tip as for limit orders.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{close_account, transfer, CloseAccount, Mint, Token, TokenAccount, Transfer},
+};
+
+use crate::math::U256;
+use crate::orders::KEEPER_TIP_BPS;
+use crate::twamm::{self, Twamm};
+use crate::permission::{self, AllowlistEntry};
+use crate::migrate::ReadPool;
+use crate::{ErrorCode, PoolInfo};
+
+#[account]
+pub struct Dca {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub dca_id: u64,
+    // Token A in for token B out, as in `buy`, or the reverse as in `sell`
+    pub input_is_a: bool,
+    pub input_mint: Pubkey,
+    pub output_mint: Pubkey,
+    pub amount_per_cycle: u64,
+    // Seconds between cycles
+    pub frequency: i64,
+    pub next_cycle_ts: i64,
+    // Most a cycle may fall short of the TWAP, in bps
+    pub max_slippage_bps: u64,
+    // Pool price accumulator at the previous cycle
+    pub price_cumulative: u128,
+    pub price_cumulative_ts: i64,
+    pub bump: u8,
+}
+
+pub fn create_dca(
+    ctx: Context<CreateDca>,
+    dca_id: u64,
+    total_amount: u64,
+    amount_per_cycle: u64,
+    frequency: i64,
+    max_slippage_bps: u64,
+    input_is_a: bool,
+) -> Result<()> {
+    if total_amount == 0
+        || amount_per_cycle == 0
+        || amount_per_cycle > total_amount
+        || frequency <= 0
+        || max_slippage_bps > 10000
+    {
+        return Err(ErrorCode::InvalidDcaParameters.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    let pool = ctx.accounts.pool.read()?;
+    permission::check_access(
+        &pool,
+        &ctx.accounts.owner.key(),
+        &ctx.accounts.allowlist_entry,
+        &ctx.accounts.gate_token,
+    )?;
+    let (input_mint, output_mint) = if input_is_a {
+        (pool.mint_a, pool.mint_b)
+    } else {
+        (pool.mint_b, pool.mint_a)
+    };
+    require_keys_eq!(ctx.accounts.input_mint.key(), input_mint, ErrorCode::InvalidOrderMint);
+
+    let dca = &mut ctx.accounts.dca;
+    dca.pool = ctx.accounts.pool.key();
+    dca.owner = ctx.accounts.owner.key();
+    dca.dca_id = dca_id;
+    dca.input_is_a = input_is_a;
+    dca.input_mint = input_mint;
+    dca.output_mint = output_mint;
+    dca.amount_per_cycle = amount_per_cycle;
+    dca.frequency = frequency;
+    dca.next_cycle_ts = now + frequency;
+    dca.max_slippage_bps = max_slippage_bps;
+    dca.price_cumulative = pool.price_cumulative_at(now);
+    dca.price_cumulative_ts = now;
+    dca.bump = ctx.bumps.dca;
+    drop(pool);
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.owner_input_token.to_account_info(),
+                to: ctx.accounts.dca_vault.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        ),
+        total_amount,
+    )?;
+
+    msg!(
+        "DCA Created: {} tokens of {:?}, {} every {}s",
+        total_amount, input_mint, amount_per_cycle, frequency
+    );
+
+    Ok(())
+}
+
+// Swap one cycle. Closes the DCA once its escrow is spent.
+pub fn execute_dca(ctx: Context<ExecuteDca>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    if now < ctx.accounts.dca.next_cycle_ts {
+        return Err(ErrorCode::DcaNotDue.into());
+    }
+
+    let accounts = &mut *ctx.accounts;
+    twamm::settle_if_needed(
+        &accounts.pool,
+        (&mut accounts.pool_token_a, &mut accounts.pool_token_b),
+        &accounts.twamm,
+        (&mut accounts.twamm_vault_a, &mut accounts.twamm_vault_b),
+        &accounts.token_program,
+    )?;
+
+    let dca = &accounts.dca;
+    let input_is_a = dca.input_is_a;
+    let amount = dca.amount_per_cycle.min(accounts.dca_vault.amount);
+    require!(amount > 0, ErrorCode::InvalidAmount);
+
+    // TWAP since the previous cycle, token A priced in token B
+    let pool = accounts.pool.load()?;
+    let price_cumulative = pool.price_cumulative_at(now);
+    let elapsed = (now - dca.price_cumulative_ts) as u128;
+    let twap = price_cumulative.wrapping_sub(dca.price_cumulative) / elapsed;
+    if twap == 0 {
+        return Err(ErrorCode::TwapUnavailable.into());
+    }
+    let expected_output = if input_is_a {
+        (U256::from(amount) * U256::from(twap)) >> 64
+    } else {
+        (U256::from(amount) << 64) / U256::from(twap)
+    };
+    let min_output = u128::try_from(expected_output * U256::from(10000 - dca.max_slippage_bps) / U256::from(10000u64))
+        .map_err(|_| ErrorCode::Overflow)?;
+
+    let (pool_input, pool_output) = if input_is_a {
+        (&accounts.pool_token_a, &accounts.pool_token_b)
+    } else {
+        (&accounts.pool_token_b, &accounts.pool_token_a)
+    };
+    let input_reserve = pool_input.amount as u128;
+    let output_reserve = pool_output.amount as u128;
+    let (output, fee_amount) = pool.quote_swap(
+        input_reserve,
+        output_reserve,
+        amount as u128,
+        input_is_a,
+        now,
+    )?;
+    let tip = output * KEEPER_TIP_BPS / 10000;
+    if output - tip < min_output {
+        msg!("DCA cycle below TWAP: {} < {}", output - tip, min_output);
+        return Err(ErrorCode::InsufficientOutputAmount.into());
+    }
+
+    let pool_key = dca.pool;
+    let owner = dca.owner;
+    let dca_id = dca.dca_id.to_le_bytes();
+    let dca_seeds: &[&[u8]] = &[b"dca", pool_key.as_ref(), owner.as_ref(), &dca_id, &[dca.bump]];
+    let dca_signer = &[dca_seeds];
+    let mint_b = pool.mint_b;
+    let pool_bump = pool.bump;
+    // The pool signs the transfers below
+    drop(pool);
+    let pool_seeds: &[&[u8]] = &[b"pool", mint_b.as_ref(), &[pool_bump]];
+    let pool_signer = &[pool_seeds];
+
+    transfer(
+        CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            Transfer {
+                from: accounts.dca_vault.to_account_info(),
+                to: pool_input.to_account_info(),
+                authority: accounts.dca.to_account_info(),
+            },
+            dca_signer,
+        ),
+        amount,
+    )?;
+
+    for (to, amount) in [
+        (accounts.owner_output_token.to_account_info(), output - tip),
+        (accounts.keeper_output_token.to_account_info(), tip),
+    ] {
+        if amount > 0 {
+            transfer(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: pool_output.to_account_info(),
+                        to,
+                        authority: accounts.pool.to_account_info(),
+                    },
+                    pool_signer,
+                ),
+                amount as u64,
+            )?;
+        }
+    }
+
+    accounts.pool_token_a.reload()?;
+    accounts.pool_token_b.reload()?;
+    let balances = if input_is_a {
+        (accounts.pool_token_a.amount as u128, accounts.pool_token_b.amount as u128)
+    } else {
+        (accounts.pool_token_b.amount as u128, accounts.pool_token_a.amount as u128)
+    };
+    accounts.pool.load_mut()?.settle_swap(
+        (input_reserve, output_reserve),
+        balances,
+        fee_amount,
+        input_is_a,
+        now,
+    )?;
+
+    let dca = &mut accounts.dca;
+    dca.next_cycle_ts = now + dca.frequency;
+    dca.price_cumulative = price_cumulative;
+    dca.price_cumulative_ts = now;
+
+    msg!("DCA Cycle Executed: {} in, {} out, keeper tip {}", amount, output - tip, tip);
+
+    accounts.dca_vault.reload()?;
+    if accounts.dca_vault.amount == 0 {
+        close_account(CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: accounts.dca_vault.to_account_info(),
+                destination: accounts.owner.to_account_info(),
+                authority: accounts.dca.to_account_info(),
+            },
+            dca_signer,
+        ))?;
+        accounts.dca.close(accounts.owner.to_account_info())?;
+        msg!("DCA Completed");
+    }
+
+    Ok(())
+}
+
+pub fn cancel_dca(ctx: Context<CancelDca>) -> Result<()> {
+    let dca = &ctx.accounts.dca;
+    let pool = dca.pool;
+    let owner = dca.owner;
+    let dca_id = dca.dca_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"dca", pool.as_ref(), owner.as_ref(), &dca_id, &[dca.bump]];
+    let signer = &[seeds];
+
+    let amount = ctx.accounts.dca_vault.amount;
+    if amount > 0 {
+        transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.dca_vault.to_account_info(),
+                    to: ctx.accounts.owner_input_token.to_account_info(),
+                    authority: ctx.accounts.dca.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+        )?;
+    }
+
+    close_account(CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        CloseAccount {
+            account: ctx.accounts.dca_vault.to_account_info(),
+            destination: ctx.accounts.owner.to_account_info(),
+            authority: ctx.accounts.dca.to_account_info(),
+        },
+        signer,
+    ))?;
+
+    msg!("DCA Cancelled: returned {} tokens", amount);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(dca_id: u64)]
+pub struct CreateDca<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.read()?.mint_b.as_ref()
+        ],
+        bump = pool.read()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        init,
+        seeds = [
+            b"dca",
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            &dca_id.to_le_bytes()
+        ],
+        bump,
+        payer = owner,
+        space = 8 + std::mem::size_of::<Dca>()
+    )]
+    pub dca: Box<Account<'info, Dca>>,
+
+    pub input_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            b"dca_vault",
+            dca.key().as_ref()
+        ],
+        bump,
+        token::mint = input_mint,
+        token::authority = dca,
+    )]
+    pub dca_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = owner_input_token.owner == owner.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = owner_input_token.mint == input_mint.key() @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub owner_input_token: Box<Account<'info, TokenAccount>>,
+
+    // Only required in permissioned pools, see `permission.rs`
+    #[account(
+        seeds = [
+            b"allowlist",
+            pool.key().as_ref(),
+            owner.key().as_ref()
+        ],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteDca<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"dca",
+            dca.pool.as_ref(),
+            dca.owner.as_ref(),
+            &dca.dca_id.to_le_bytes()
+        ],
+        bump = dca.bump,
+        has_one = pool,
+        has_one = owner,
+        has_one = output_mint @ ErrorCode::InvalidOrderMint,
+    )]
+    pub dca: Box<Account<'info, Dca>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"dca_vault",
+            dca.key().as_ref()
+        ],
+        bump,
+    )]
+    pub dca_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    pub output_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = output_mint,
+        associated_token::authority = owner,
+    )]
+    pub owner_output_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        associated_token::mint = output_mint,
+        associated_token::authority = keeper,
+    )]
+    pub keeper_output_token: Box<Account<'info, TokenAccount>>,
+
+    // Only required while the pool has open long-term orders
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: Option<AccountLoader<'info, Twamm>>,
+    #[account(mut)]
+    pub twamm_vault_a: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(mut)]
+    pub twamm_vault_b: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// CHECK: DCA owner, receives the rent once the escrow is spent
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct CancelDca<'info> {
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::Unauthorized,
+        close = owner,
+    )]
+    pub dca: Box<Account<'info, Dca>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"dca_vault",
+            dca.key().as_ref()
+        ],
+        bump,
+    )]
+    pub dca_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = owner_input_token.owner == owner.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = owner_input_token.mint == dca.input_mint @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub owner_input_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
@@ -0,0 +1,653 @@
+// LP staking farms.
+//
+// A farm pays out one reward token to everyone staking a pool's LP tokens,
+// at a fixed emission rate shared pro rata to stake. Rewards are prefunded into
+// the farm's vault and emissions stop once the funding runs out. Staked LP and
+// rewards sit in token accounts at `[b"farm_vault", farm, mint]`, which, unlike
+// associated token accounts, nobody can create ahead of `create_farm`.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{transfer, Mint, Token, TokenAccount, Transfer},
+};
+
+use crate::math::U256;
+use crate::migrate::ReadPool;
+use crate::{ErrorCode, PoolInfo, ADMIN_PUBKEY};
+
+#[account]
+pub struct Farm {
+    pub pool: Pubkey,
+    pub lp_mint: Pubkey,
+    pub reward_mint: Pubkey,
+    // Reward tokens emitted per second across all stakers
+    pub reward_per_second: u64,
+    // Emissions stop here; pushed back whenever the farm is funded
+    pub end_ts: i64,
+    pub last_update_ts: i64,
+    // Rewards earned per staked LP token over the farm's lifetime, Q64.64
+    pub reward_per_share: u128,
+    pub total_staked: u64,
+}
+
+impl Farm {
+    // Accrue emissions up to `now`. Time with nothing staked emits nothing.
+    pub fn update(&mut self, now: i64) -> Result<()> {
+        let until = now.min(self.end_ts);
+        if until <= self.last_update_ts {
+            return Ok(());
+        }
+
+        if self.total_staked > 0 {
+            let elapsed = (until - self.last_update_ts) as u128;
+            let rewards = elapsed
+                .checked_mul(self.reward_per_second as u128)
+                .ok_or(ErrorCode::Overflow)?;
+            let growth = (U256::from(rewards) << 64)
+                .checked_div(U256::from(self.total_staked))
+                .and_then(|growth| u128::try_from(growth).ok())
+                .ok_or(ErrorCode::Overflow)?;
+            self.reward_per_share = self
+                .reward_per_share
+                .checked_add(growth)
+                .ok_or(ErrorCode::Overflow)?;
+        }
+        self.last_update_ts = until;
+
+        Ok(())
+    }
+
+    // Rewards earned by `amount` staked LP tokens since the farm started
+    pub fn accrued(&self, amount: u64) -> Result<u128> {
+        let accrued = U256::from(amount)
+            .checked_mul(U256::from(self.reward_per_share))
+            .ok_or(ErrorCode::Overflow)?
+            >> 64;
+        u128::try_from(accrued).map_err(|_| ErrorCode::Overflow.into())
+    }
+
+    // Push `end_ts` back by however long `amount` more rewards last. Call
+    // `update` first: a farm that already ran dry restarts emissions at `now`,
+    // since the gap since `end_ts` was never funded.
+    pub fn extend(&mut self, amount: u64, now: i64) -> Result<()> {
+        if self.end_ts < now {
+            self.last_update_ts = now;
+        }
+        let duration = (amount / self.reward_per_second) as i64;
+        self.end_ts = self
+            .end_ts
+            .max(now)
+            .checked_add(duration)
+            .ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+pub struct UserStake {
+    pub farm: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    // `farm.accrued(amount)` at the last update
+    pub reward_debt: u128,
+    pub rewards_owed: u64,
+}
+
+impl UserStake {
+    // Settle rewards earned since the last update, then apply the stake change
+    pub fn update(&mut self, farm: &Farm, new_amount: u64) -> Result<()> {
+        let earned = farm
+            .accrued(self.amount)?
+            .checked_sub(self.reward_debt)
+            .and_then(|earned| u64::try_from(earned).ok())
+            .ok_or(ErrorCode::Overflow)?;
+
+        self.rewards_owed = self.rewards_owed.checked_add(earned).ok_or(ErrorCode::Overflow)?;
+        self.amount = new_amount;
+        self.reward_debt = farm.accrued(new_amount)?;
+
+        Ok(())
+    }
+}
+
+pub fn create_farm(ctx: Context<CreateFarm>, reward_per_second: u64, reward_amount: u64) -> Result<()> {
+    if reward_per_second == 0 || reward_amount < reward_per_second {
+        return Err(ErrorCode::InvalidEmissionRate.into());
+    }
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.admin_reward_token.to_account_info(),
+                to: ctx.accounts.reward_vault.to_account_info(),
+                authority: ctx.accounts.admin.to_account_info(),
+            },
+        ),
+        reward_amount,
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let farm = &mut ctx.accounts.farm;
+    farm.pool = ctx.accounts.pool.key();
+    farm.lp_mint = ctx.accounts.lp_mint.key();
+    farm.reward_mint = ctx.accounts.reward_mint.key();
+    farm.reward_per_second = reward_per_second;
+    farm.end_ts = now;
+    farm.last_update_ts = now;
+    farm.reward_per_share = 0;
+    farm.total_staked = 0;
+    farm.extend(reward_amount, now)?;
+
+    msg!(
+        "Farm Created for pool {:?}: {} reward tokens per second until {}",
+        farm.pool, reward_per_second, farm.end_ts
+    );
+
+    Ok(())
+}
+
+// Anyone can top up a farm's rewards, which extends its emissions
+pub fn fund_farm(ctx: Context<FundFarm>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::InvalidAmount);
+
+    let now = Clock::get()?.unix_timestamp;
+    let farm = &mut ctx.accounts.farm;
+    farm.update(now)?;
+    farm.extend(amount, now)?;
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.funder_reward_token.to_account_info(),
+                to: ctx.accounts.reward_vault.to_account_info(),
+                authority: ctx.accounts.funder.to_account_info(),
+            },
+        ),
+        amount,
+    )?;
+
+    msg!("Farm Funded with {} reward tokens, emitting until {}", amount, ctx.accounts.farm.end_ts);
+
+    Ok(())
+}
+
+pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::InvalidAmount);
+
+    let now = Clock::get()?.unix_timestamp;
+    let farm = &mut ctx.accounts.farm;
+    farm.update(now)?;
+
+    let user_stake = &mut ctx.accounts.user_stake;
+    user_stake.farm = farm.key();
+    user_stake.owner = ctx.accounts.owner.key();
+    let new_amount = user_stake.amount.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+    user_stake.update(farm, new_amount)?;
+    farm.total_staked = farm.total_staked.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.user_lp_token.to_account_info(),
+                to: ctx.accounts.lp_vault.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        ),
+        amount,
+    )?;
+
+    msg!("Staked {} LP tokens, {} staked in total", amount, new_amount);
+
+    Ok(())
+}
+
+pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::InvalidAmount);
+
+    let now = Clock::get()?.unix_timestamp;
+    let farm = &mut ctx.accounts.farm;
+    farm.update(now)?;
+
+    let user_stake = &mut ctx.accounts.user_stake;
+    let new_amount = user_stake
+        .amount
+        .checked_sub(amount)
+        .ok_or(ErrorCode::InsufficientStake)?;
+    user_stake.update(farm, new_amount)?;
+    farm.total_staked = farm.total_staked.checked_sub(amount).ok_or(ErrorCode::Overflow)?;
+
+    let pool = farm.pool;
+    let reward_mint = farm.reward_mint;
+    let seeds: &[&[u8]] = &[
+        b"farm",
+        pool.as_ref(),
+        reward_mint.as_ref(),
+        &[ctx.bumps.farm],
+    ];
+    let signer = &[seeds];
+
+    transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.lp_vault.to_account_info(),
+                to: ctx.accounts.user_lp_token.to_account_info(),
+                authority: ctx.accounts.farm.to_account_info(),
+            },
+            signer,
+        ),
+        amount,
+    )?;
+
+    msg!("Unstaked {} LP tokens, {} still staked", amount, new_amount);
+
+    Ok(())
+}
+
+pub fn claim(ctx: Context<Claim>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let farm = &mut ctx.accounts.farm;
+    farm.update(now)?;
+
+    let user_stake = &mut ctx.accounts.user_stake;
+    let amount = user_stake.amount;
+    user_stake.update(farm, amount)?;
+    let rewards = user_stake.rewards_owed;
+    user_stake.rewards_owed = 0;
+
+    if rewards > 0 {
+        let pool = farm.pool;
+        let reward_mint = farm.reward_mint;
+        let seeds: &[&[u8]] = &[
+            b"farm",
+            pool.as_ref(),
+            reward_mint.as_ref(),
+            &[ctx.bumps.farm],
+        ];
+        let signer = &[seeds];
+
+        transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    to: ctx.accounts.user_reward_token.to_account_info(),
+                    authority: ctx.accounts.farm.to_account_info(),
+                },
+                signer,
+            ),
+            rewards,
+        )?;
+    }
+
+    msg!("Claimed {} reward tokens", rewards);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateFarm<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.read()?.mint_b.as_ref()
+        ],
+        bump = pool.read()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.read()?.lp_mint @ ErrorCode::InvalidLPMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+    // The LP and reward vaults are both derived from the farm and their mint, so the mints must differ
+    #[account(constraint = reward_mint.key() != lp_mint.key() @ ErrorCode::InvalidRewardMint)]
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"farm",
+            pool.key().as_ref(),
+            reward_mint.key().as_ref()
+        ],
+        bump,
+        payer = admin,
+        space = 8 + std::mem::size_of::<Farm>()
+    )]
+    pub farm: Box<Account<'info, Farm>>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [
+            b"farm_vault",
+            farm.key().as_ref(),
+            lp_mint.key().as_ref()
+        ],
+        bump,
+        token::mint = lp_mint,
+        token::authority = farm,
+    )]
+    pub lp_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = admin,
+        seeds = [
+            b"farm_vault",
+            farm.key().as_ref(),
+            reward_mint.key().as_ref()
+        ],
+        bump,
+        token::mint = reward_mint,
+        token::authority = farm,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = admin_reward_token.owner == admin.key() @ ErrorCode::Unauthorized,
+        constraint = admin_reward_token.mint == reward_mint.key() @ ErrorCode::InvalidRewardMint
+    )]
+    pub admin_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundFarm<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"farm",
+            farm.pool.as_ref(),
+            farm.reward_mint.as_ref()
+        ],
+        bump,
+    )]
+    pub farm: Box<Account<'info, Farm>>,
+
+    #[account(address = farm.reward_mint @ ErrorCode::InvalidRewardMint)]
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"farm_vault",
+            farm.key().as_ref(),
+            reward_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = funder_reward_token.owner == funder.key() @ ErrorCode::Unauthorized,
+        constraint = funder_reward_token.mint == farm.reward_mint @ ErrorCode::InvalidRewardMint
+    )]
+    pub funder_reward_token: Box<Account<'info, TokenAccount>>,
+
+    pub funder: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"farm",
+            farm.pool.as_ref(),
+            farm.reward_mint.as_ref()
+        ],
+        bump,
+    )]
+    pub farm: Box<Account<'info, Farm>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [
+            b"stake",
+            farm.key().as_ref(),
+            owner.key().as_ref()
+        ],
+        bump,
+        payer = owner,
+        space = 8 + std::mem::size_of::<UserStake>()
+    )]
+    pub user_stake: Box<Account<'info, UserStake>>,
+
+    #[account(address = farm.lp_mint @ ErrorCode::InvalidLPMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"farm_vault",
+            farm.key().as_ref(),
+            lp_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub lp_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_lp_token.owner == owner.key() @ ErrorCode::InvalidUserLPOwner,
+        constraint = user_lp_token.mint == farm.lp_mint @ ErrorCode::InvalidUserLPMint
+    )]
+    pub user_lp_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"farm",
+            farm.pool.as_ref(),
+            farm.reward_mint.as_ref()
+        ],
+        bump,
+    )]
+    pub farm: Box<Account<'info, Farm>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"stake",
+            farm.key().as_ref(),
+            owner.key().as_ref()
+        ],
+        bump,
+        has_one = farm,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Box<Account<'info, UserStake>>,
+
+    #[account(address = farm.lp_mint @ ErrorCode::InvalidLPMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"farm_vault",
+            farm.key().as_ref(),
+            lp_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub lp_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_lp_token.owner == owner.key() @ ErrorCode::InvalidUserLPOwner,
+        constraint = user_lp_token.mint == farm.lp_mint @ ErrorCode::InvalidUserLPMint
+    )]
+    pub user_lp_token: Box<Account<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Claim<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"farm",
+            farm.pool.as_ref(),
+            farm.reward_mint.as_ref()
+        ],
+        bump,
+    )]
+    pub farm: Box<Account<'info, Farm>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"stake",
+            farm.key().as_ref(),
+            owner.key().as_ref()
+        ],
+        bump,
+        has_one = farm,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub user_stake: Box<Account<'info, UserStake>>,
+
+    #[account(address = farm.reward_mint @ ErrorCode::InvalidRewardMint)]
+    pub reward_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"farm_vault",
+            farm.key().as_ref(),
+            reward_mint.key().as_ref()
+        ],
+        bump,
+    )]
+    pub reward_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = reward_mint,
+        associated_token::authority = owner,
+    )]
+    pub user_reward_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn farm(reward_per_second: u64, amount: u64, now: i64) -> Farm {
+        let mut farm = Farm {
+            pool: Pubkey::default(),
+            lp_mint: Pubkey::default(),
+            reward_mint: Pubkey::default(),
+            reward_per_second,
+            end_ts: now,
+            last_update_ts: now,
+            reward_per_share: 0,
+            total_staked: 0,
+        };
+        farm.extend(amount, now).unwrap();
+        farm
+    }
+
+    #[test]
+    fn emits_funding_pro_rata() {
+        let mut farm = farm(10, 1_000, 0);
+        farm.total_staked = 300;
+        farm.update(50).unwrap();
+        // 500 emitted over 300 staked
+        assert_eq!(farm.accrued(100).unwrap(), 166);
+        assert_eq!(farm.accrued(200).unwrap(), 333);
+
+        // Nothing past the funded end
+        farm.update(1_000).unwrap();
+        assert_eq!(farm.last_update_ts, 100);
+        assert_eq!(farm.accrued(300).unwrap(), 999);
+    }
+
+    #[test]
+    fn fund_after_expiry_skips_unfunded_gap() {
+        let mut farm = farm(10, 1_000, 0);
+        farm.total_staked = 100;
+        farm.update(100).unwrap();
+        assert_eq!(farm.end_ts, 100);
+
+        // Topped up long after running dry, as `fund_farm` does
+        let now = 500;
+        farm.update(now).unwrap();
+        farm.extend(1_000, now).unwrap();
+        assert_eq!(farm.last_update_ts, now);
+        assert_eq!(farm.end_ts, 600);
+
+        // The gap [100, 500] emitted nothing, so stakers can't earn more than was funded
+        farm.update(550).unwrap();
+        assert_eq!(farm.accrued(100).unwrap(), 1_500);
+        farm.update(10_000).unwrap();
+        assert_eq!(farm.accrued(100).unwrap(), 2_000);
+    }
+
+    #[test]
+    fn fund_before_expiry_extends_end() {
+        let mut farm = farm(10, 1_000, 0);
+        farm.total_staked = 100;
+        farm.update(40).unwrap();
+        farm.extend(500, 40).unwrap();
+        assert_eq!(farm.last_update_ts, 40);
+        assert_eq!(farm.end_ts, 150);
+
+        farm.update(10_000).unwrap();
+        assert_eq!(farm.accrued(100).unwrap(), 1_500);
+    }
+
+    #[test]
+    fn stake_changes_settle_rewards() {
+        let mut farm = farm(10, 10_000, 0);
+        let mut alice = UserStake {
+            farm: Pubkey::default(),
+            owner: Pubkey::default(),
+            amount: 0,
+            reward_debt: 0,
+            rewards_owed: 0,
+        };
+        alice.update(&farm, 100).unwrap();
+        farm.total_staked = 100;
+
+        farm.update(10).unwrap();
+        alice.update(&farm, 0).unwrap();
+        farm.total_staked = 0;
+        assert_eq!(alice.rewards_owed, 100);
+
+        // Unstaked time earns nothing
+        farm.update(20).unwrap();
+        alice.update(&farm, 0).unwrap();
+        assert_eq!(alice.rewards_owed, 100);
+    }
+}
@@ -0,0 +1,796 @@
+// Bonding-curve launchpad.
+//
+// `create_launch` mints a fixed supply of a new token. Most of it is sold on a
+// constant product curve against virtual reserves, so the price starts at
+// `virtual_quote_reserve / LAUNCH_TOTAL_SUPPLY` and rises with every buy. Once
+// the curve's market cap reaches the launch's threshold, trading stops and
+// anyone can `graduate` it into a regular babyswap pool holding the raised
+// quote tokens and the rest of the supply at the curve's final price. If the
+// rest of the supply can't match all of the raise at that price, the pool gets
+// as much quote as it can and the creator the remainder.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    metadata::{
+        create_metadata_accounts_v3, mpl_token_metadata::types::DataV2, CreateMetadataAccountsV3,
+        Metadata,
+    },
+    token::{
+        burn, mint_to, set_authority, spl_token::instruction::AuthorityType, transfer, Burn, Mint,
+        MintTo, SetAuthority, Token, TokenAccount, Transfer,
+    },
+};
+
+use crate::math::constant_product_output;
+use crate::registry::{PoolRegistry, PoolRegistryEntry};
+use crate::{create_lp_metadata, token_symbol, ErrorCode, PoolInfo};
+
+pub const LAUNCH_DECIMALS: u8 = 6;
+// 1B tokens, 800M of which are sold on the curve and the rest seeded into the pool
+pub const LAUNCH_TOTAL_SUPPLY: u64 = 1_000_000_000_000_000;
+pub const LAUNCH_CURVE_SUPPLY: u64 = 800_000_000_000_000;
+// Fee of the pool a launch graduates into (0.3%)
+pub const LAUNCH_POOL_FEE: u64 = 30;
+
+#[account]
+pub struct Launch {
+    pub creator: Pubkey,
+    pub token_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    // Curve reserves, real balances plus the virtual liquidity the curve starts with
+    pub virtual_quote_reserve: u64,
+    pub virtual_token_reserve: u64,
+    // Quote raised and tokens left to sell on the curve
+    pub real_quote_reserve: u64,
+    pub real_token_reserve: u64,
+    // Curve market cap, in quote tokens, at which trading stops
+    pub graduation_market_cap: u64,
+    pub complete: bool,
+    // The babyswap pool the launch graduated into, default until then
+    pub pool: Pubkey,
+}
+
+impl Launch {
+    // Price of the whole supply at the current curve price
+    pub fn market_cap(&self) -> Result<u64> {
+        (self.virtual_quote_reserve as u128)
+            .checked_mul(LAUNCH_TOTAL_SUPPLY as u128)
+            .and_then(|v| v.checked_div(self.virtual_token_reserve as u128))
+            .and_then(|v| u64::try_from(v).ok())
+            .ok_or(ErrorCode::Overflow.into())
+    }
+
+    // Quote and tokens to seed the graduated pool with at the curve's final
+    // price. When the tokens left over from the curve can't match all of the
+    // raised quote, the quote side is cut down to them and the rest returned
+    // to the creator, which is the third value.
+    pub fn graduation_amounts(&self) -> Result<(u64, u64, u64)> {
+        let raised = self.real_quote_reserve;
+        let max_tokens = (LAUNCH_TOTAL_SUPPLY - LAUNCH_CURVE_SUPPLY) as u128;
+        let tokens = (raised as u128)
+            .checked_mul(self.virtual_token_reserve as u128)
+            .and_then(|v| v.checked_div(self.virtual_quote_reserve as u128))
+            .ok_or(ErrorCode::Overflow)?;
+        if tokens <= max_tokens {
+            return Ok((raised, tokens as u64, 0));
+        }
+        // Rounds down, so the pool never opens above the curve
+        let quote = max_tokens
+            .checked_mul(self.virtual_quote_reserve as u128)
+            .and_then(|v| v.checked_div(self.virtual_token_reserve as u128))
+            .ok_or(ErrorCode::Overflow)? as u64;
+        Ok((quote, max_tokens as u64, raised - quote))
+    }
+}
+
+// Whether `mint` is still owned by its launch, i.e. trading on the bonding curve
+pub fn is_launch_mint(mint: &Account<Mint>, program_id: &Pubkey) -> bool {
+    let (launch, _) = Pubkey::find_program_address(&[b"launch", mint.key().as_ref()], program_id);
+    mint.mint_authority == COption::Some(launch)
+}
+
+pub fn create_launch(
+    ctx: Context<CreateLaunch>,
+    name: String,
+    symbol: String,
+    uri: String,
+    virtual_quote_reserve: u64,
+    graduation_market_cap: u64,
+) -> Result<()> {
+    // The curve starts at a market cap of `virtual_quote_reserve`
+    if virtual_quote_reserve == 0 || graduation_market_cap <= virtual_quote_reserve {
+        return Err(ErrorCode::InvalidLaunchParameters.into());
+    }
+
+    let token_mint = ctx.accounts.token_mint.key();
+    let seeds: &[&[u8]] = &[b"launch", token_mint.as_ref(), &[ctx.bumps.launch]];
+    let signer = &[seeds];
+
+    // The launch keeps mint authority until graduation, which marks the token
+    // as not yet tradable in regular pools
+    mint_to(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.token_mint.to_account_info(),
+                to: ctx.accounts.launch_token_vault.to_account_info(),
+                authority: ctx.accounts.launch.to_account_info(),
+            },
+            signer,
+        ),
+        LAUNCH_TOTAL_SUPPLY,
+    )?;
+
+    create_metadata_accounts_v3(
+        CpiContext::new_with_signer(
+            ctx.accounts.metadata_program.to_account_info(),
+            CreateMetadataAccountsV3 {
+                metadata: ctx.accounts.token_metadata.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+                mint_authority: ctx.accounts.launch.to_account_info(),
+                payer: ctx.accounts.creator.to_account_info(),
+                update_authority: ctx.accounts.launch.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                rent: ctx.accounts.rent.to_account_info(),
+            },
+            signer,
+        ),
+        DataV2 {
+            name: name.clone(),
+            symbol: symbol.clone(),
+            uri,
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        },
+        false,
+        true,
+        None,
+    )?;
+
+    let launch = &mut ctx.accounts.launch;
+    launch.creator = ctx.accounts.creator.key();
+    launch.token_mint = token_mint;
+    launch.quote_mint = ctx.accounts.quote_mint.key();
+    launch.virtual_quote_reserve = virtual_quote_reserve;
+    launch.virtual_token_reserve = LAUNCH_TOTAL_SUPPLY;
+    launch.real_quote_reserve = 0;
+    launch.real_token_reserve = LAUNCH_CURVE_SUPPLY;
+    launch.graduation_market_cap = graduation_market_cap;
+    launch.complete = false;
+    launch.pool = Pubkey::default();
+
+    msg!(
+        "Launch Created: {} ({}) mint {:?}, graduates at a market cap of {}",
+        name, symbol, token_mint, graduation_market_cap
+    );
+
+    Ok(())
+}
+
+pub fn launch_buy(ctx: Context<LaunchTrade>, quote_amount: u64, min_tokens_out: u64) -> Result<()> {
+    require!(quote_amount > 0, ErrorCode::InvalidSwapAmount);
+    let launch = &mut ctx.accounts.launch;
+
+    let mut tokens_out = constant_product_output(
+        launch.virtual_quote_reserve as u128,
+        launch.virtual_token_reserve as u128,
+        quote_amount as u128,
+    )
+    .ok_or(ErrorCode::Overflow)?;
+    let mut quote_in = quote_amount as u128;
+
+    // The last buy only pays for what is left on the curve
+    if tokens_out > launch.real_token_reserve as u128 {
+        tokens_out = launch.real_token_reserve as u128;
+        let remaining = (launch.virtual_token_reserve as u128) - tokens_out;
+        quote_in = (launch.virtual_quote_reserve as u128)
+            .checked_mul(tokens_out)
+            .and_then(|v| v.checked_add(remaining - 1))
+            .and_then(|v| v.checked_div(remaining))
+            .ok_or(ErrorCode::Overflow)?;
+    }
+    let tokens_out = tokens_out as u64;
+    let quote_in = quote_in as u64;
+    if tokens_out < min_tokens_out || tokens_out == 0 {
+        return Err(ErrorCode::InsufficientOutputAmount.into());
+    }
+
+    launch.virtual_quote_reserve = launch.virtual_quote_reserve.checked_add(quote_in).ok_or(ErrorCode::Overflow)?;
+    launch.virtual_token_reserve -= tokens_out;
+    launch.real_quote_reserve = launch.real_quote_reserve.checked_add(quote_in).ok_or(ErrorCode::Overflow)?;
+    launch.real_token_reserve -= tokens_out;
+
+    let market_cap = launch.market_cap()?;
+    if market_cap >= launch.graduation_market_cap || launch.real_token_reserve == 0 {
+        launch.complete = true;
+        msg!("Launch complete at a market cap of {}", market_cap);
+    }
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.user_quote_token.to_account_info(),
+                to: ctx.accounts.launch_quote_vault.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            },
+        ),
+        quote_in,
+    )?;
+
+    let token_mint = ctx.accounts.launch.token_mint;
+    let seeds: &[&[u8]] = &[b"launch", token_mint.as_ref(), &[ctx.bumps.launch]];
+    let signer = &[seeds];
+
+    transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.launch_token_vault.to_account_info(),
+                to: ctx.accounts.user_token.to_account_info(),
+                authority: ctx.accounts.launch.to_account_info(),
+            },
+            signer,
+        ),
+        tokens_out,
+    )?;
+
+    msg!("Launch Buy: {} tokens for {} quote tokens", tokens_out, quote_in);
+
+    Ok(())
+}
+
+pub fn launch_sell(ctx: Context<LaunchTrade>, token_amount: u64, min_quote_out: u64) -> Result<()> {
+    require!(token_amount > 0, ErrorCode::InvalidSwapAmount);
+    let launch = &mut ctx.accounts.launch;
+
+    let quote_out = constant_product_output(
+        launch.virtual_token_reserve as u128,
+        launch.virtual_quote_reserve as u128,
+        token_amount as u128,
+    )
+    .and_then(|v| u64::try_from(v).ok())
+    .ok_or(ErrorCode::Overflow)?;
+    if quote_out < min_quote_out || quote_out == 0 {
+        return Err(ErrorCode::InsufficientOutputAmount.into());
+    }
+
+    launch.virtual_token_reserve = launch.virtual_token_reserve.checked_add(token_amount).ok_or(ErrorCode::Overflow)?;
+    launch.virtual_quote_reserve -= quote_out;
+    launch.real_token_reserve = launch.real_token_reserve.checked_add(token_amount).ok_or(ErrorCode::Overflow)?;
+    launch.real_quote_reserve = launch
+        .real_quote_reserve
+        .checked_sub(quote_out)
+        .ok_or(ErrorCode::InsufficientLiquidity)?;
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.user_token.to_account_info(),
+                to: ctx.accounts.launch_token_vault.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            },
+        ),
+        token_amount,
+    )?;
+
+    let token_mint = ctx.accounts.launch.token_mint;
+    let seeds: &[&[u8]] = &[b"launch", token_mint.as_ref(), &[ctx.bumps.launch]];
+    let signer = &[seeds];
+
+    transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.launch_quote_vault.to_account_info(),
+                to: ctx.accounts.user_quote_token.to_account_info(),
+                authority: ctx.accounts.launch.to_account_info(),
+            },
+            signer,
+        ),
+        quote_out,
+    )?;
+
+    msg!("Launch Sell: {} tokens for {} quote tokens", token_amount, quote_out);
+
+    Ok(())
+}
+
+// Move a completed launch into a babyswap pool (quote as token A, the launched
+// token as token B). The LP for the initial liquidity is minted to a vault at
+// `[b"launch_lp", launch]` that nothing can withdraw from, so it stays locked
+// for good while the LP supply still matches `total_liquidity`. Any tokens not
+// needed for the pool are burned, and any quote not needed goes to the creator.
+pub fn graduate(ctx: Context<Graduate>) -> Result<()> {
+    let launch = &ctx.accounts.launch;
+    let (quote_amount, token_amount, excess_quote) = launch.graduation_amounts()?;
+    if quote_amount == 0 || token_amount == 0 {
+        return Err(ErrorCode::ZeroReserve.into());
+    }
+
+    let token_mint = launch.token_mint;
+    let quote_mint = launch.quote_mint;
+    let launch_seeds: &[&[u8]] = &[b"launch", token_mint.as_ref(), &[ctx.bumps.launch]];
+    let launch_signer = &[launch_seeds];
+
+    for (from, to, amount) in [
+        (&ctx.accounts.launch_quote_vault, &ctx.accounts.pool_token_a, quote_amount),
+        (&ctx.accounts.launch_token_vault, &ctx.accounts.pool_token_b, token_amount),
+        (&ctx.accounts.launch_quote_vault, &ctx.accounts.creator_quote_token, excess_quote),
+    ] {
+        if amount == 0 {
+            continue;
+        }
+        transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: from.to_account_info(),
+                    to: to.to_account_info(),
+                    authority: ctx.accounts.launch.to_account_info(),
+                },
+                launch_signer,
+            ),
+            amount,
+        )?;
+    }
+
+    ctx.accounts.launch_token_vault.reload()?;
+    let leftover = ctx.accounts.launch_token_vault.amount;
+    if leftover > 0 {
+        burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    from: ctx.accounts.launch_token_vault.to_account_info(),
+                    authority: ctx.accounts.launch.to_account_info(),
+                },
+                launch_signer,
+            ),
+            leftover,
+        )?;
+    }
+
+    // Fix the supply, which also opens the token up to regular pools
+    set_authority(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            SetAuthority {
+                current_authority: ctx.accounts.launch.to_account_info(),
+                account_or_mint: ctx.accounts.token_mint.to_account_info(),
+            },
+            launch_signer,
+        ),
+        AuthorityType::MintTokens,
+        None,
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let pool_key = ctx.accounts.pool.key();
+    let lp_mint = ctx.accounts.lp_mint.key();
+    {
+        let mut pool = ctx.accounts.pool.load_init()?;
+        pool.initialize(pool_key, quote_mint, token_mint, lp_mint, LAUNCH_POOL_FEE, now);
+        pool.set_bumps(ctx.bumps.pool, ctx.bumps.lp_mint);
+        pool.set_vaults(
+            ctx.accounts.pool_token_a.key(),
+            ctx.bumps.pool_token_a,
+            ctx.accounts.pool_token_b.key(),
+            ctx.bumps.pool_token_b,
+        );
+        // Same as a first `add_liquidity`, which issues `amount_a` LP
+        pool.total_liquidity = quote_amount as u128;
+        pool.creator = ctx.accounts.launch.creator;
+        ctx.accounts.registry.append(&mut ctx.accounts.registry_entry, &mut pool);
+    }
+
+    let pool_seeds: &[&[u8]] = &[b"pool", token_mint.as_ref(), &[ctx.bumps.pool]];
+    let pool_signer = &[pool_seeds];
+
+    mint_to(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                to: ctx.accounts.launch_lp_vault.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
+            },
+            pool_signer,
+        ),
+        quote_amount,
+    )?;
+
+    let symbol_a = token_symbol(&ctx.accounts.quote_metadata, &quote_mint);
+    let symbol_b = token_symbol(&ctx.accounts.token_metadata, &token_mint);
+    let name = create_lp_metadata(
+        CpiContext::new_with_signer(
+            ctx.accounts.metadata_program.to_account_info(),
+            CreateMetadataAccountsV3 {
+                metadata: ctx.accounts.lp_mint_metadata.to_account_info(),
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                mint_authority: ctx.accounts.pool.to_account_info(),
+                payer: ctx.accounts.payer.to_account_info(),
+                update_authority: ctx.accounts.pool.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                rent: ctx.accounts.rent.to_account_info(),
+            },
+            pool_signer,
+        ),
+        &symbol_a,
+        &symbol_b,
+    )?;
+
+    let launch = &mut ctx.accounts.launch;
+    launch.pool = pool_key;
+    launch.real_quote_reserve = 0;
+    launch.real_token_reserve = 0;
+
+    msg!(
+        "Launch Graduated into pool {:?} ({}) with {} quote tokens and {} tokens, burned {}, returned {} quote tokens, locked {} LP",
+        pool_key, name, quote_amount, token_amount, leftover, excess_quote, quote_amount
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct CreateLaunch<'info> {
+    #[account(
+        init,
+        payer = creator,
+        mint::decimals = LAUNCH_DECIMALS,
+        mint::authority = launch,
+    )]
+    pub token_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"launch",
+            token_mint.key().as_ref()
+        ],
+        bump,
+        payer = creator,
+        space = 8 + std::mem::size_of::<Launch>()
+    )]
+    pub launch: Box<Account<'info, Launch>>,
+
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = creator,
+        associated_token::mint = token_mint,
+        associated_token::authority = launch,
+    )]
+    pub launch_token_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = creator,
+        associated_token::mint = quote_mint,
+        associated_token::authority = launch,
+    )]
+    pub launch_quote_vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: Created by the token metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub token_metadata: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub metadata_program: Program<'info, Metadata>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct LaunchTrade<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"launch",
+            launch.token_mint.as_ref()
+        ],
+        bump,
+        constraint = !launch.complete @ ErrorCode::LaunchComplete,
+    )]
+    pub launch: Box<Account<'info, Launch>>,
+
+    #[account(address = launch.token_mint @ ErrorCode::InvalidPoolTokenB)]
+    pub token_mint: Box<Account<'info, Mint>>,
+    #[account(address = launch.quote_mint @ ErrorCode::InvalidPoolTokenA)]
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = launch,
+    )]
+    pub launch_token_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = quote_mint,
+        associated_token::authority = launch,
+    )]
+    pub launch_quote_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = token_mint,
+        associated_token::authority = user,
+    )]
+    pub user_token: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = user_quote_token.owner == user.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = user_quote_token.mint == quote_mint.key() @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub user_quote_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct Graduate<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"launch",
+            launch.token_mint.as_ref()
+        ],
+        bump,
+        constraint = launch.complete @ ErrorCode::LaunchNotComplete,
+        constraint = launch.pool == Pubkey::default() @ ErrorCode::LaunchNotComplete,
+    )]
+    pub launch: Box<Account<'info, Launch>>,
+
+    #[account(mut, address = launch.token_mint @ ErrorCode::InvalidPoolTokenB)]
+    pub token_mint: Box<Account<'info, Mint>>,
+    #[account(address = launch.quote_mint @ ErrorCode::InvalidPoolTokenA)]
+    pub quote_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = launch,
+    )]
+    pub launch_token_vault: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = quote_mint,
+        associated_token::authority = launch,
+    )]
+    pub launch_quote_vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: Only receives the quote tokens the pool can't take
+    #[account(address = launch.creator @ ErrorCode::Unauthorized)]
+    pub creator: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = quote_mint,
+        associated_token::authority = creator,
+    )]
+    pub creator_quote_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"pool",
+            token_mint.key().as_ref()
+        ],
+        bump,
+        payer = payer,
+        space = PoolInfo::SPACE
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"lp_mint",
+            pool.key().as_ref()
+        ],
+        bump,
+        mint::decimals = 6,
+        mint::authority = pool,
+    )]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            quote_mint.key().as_ref()
+        ],
+        bump,
+        token::mint = quote_mint,
+        token::authority = pool,
+    )]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        bump,
+        token::mint = token_mint,
+        token::authority = pool,
+    )]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    // Holds the initial LP forever, no instruction moves tokens out of it
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"launch_lp",
+            launch.key().as_ref()
+        ],
+        bump,
+        token::mint = lp_mint,
+        token::authority = launch,
+    )]
+    pub launch_lp_vault: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: Created by the token metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            lp_mint.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub lp_mint_metadata: UncheckedAccount<'info>,
+    /// CHECK: Address checked against the metadata PDA, contents parsed in `token_symbol`
+    #[account(
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            quote_mint.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub quote_metadata: UncheckedAccount<'info>,
+    /// CHECK: Address checked against the metadata PDA, contents parsed in `token_symbol`
+    #[account(
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            token_mint.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub token_metadata: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        seeds = [b"registry"],
+        bump,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PoolRegistry>()
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+    #[account(
+        init,
+        seeds = [
+            b"pool_entry".as_ref(),
+            &registry.pool_count.to_le_bytes()
+        ],
+        bump,
+        payer = payer,
+        space = 8 + std::mem::size_of::<PoolRegistryEntry>()
+    )]
+    pub registry_entry: Box<Account<'info, PoolRegistryEntry>>,
+
+    // Anyone can graduate a completed launch and pays the new accounts' rent
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub metadata_program: Program<'info, Metadata>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIRTUAL_QUOTE: u64 = 30_000_000_000;
+
+    // A launch after a single buy of `quote_in`
+    fn launch_after_buy(graduation_market_cap: u64, quote_in: u64) -> Launch {
+        let tokens_out = constant_product_output(VIRTUAL_QUOTE as u128, LAUNCH_TOTAL_SUPPLY as u128, quote_in as u128)
+            .unwrap() as u64;
+        let launch = Launch {
+            creator: Pubkey::default(),
+            token_mint: Pubkey::default(),
+            quote_mint: Pubkey::default(),
+            virtual_quote_reserve: VIRTUAL_QUOTE + quote_in,
+            virtual_token_reserve: LAUNCH_TOTAL_SUPPLY - tokens_out,
+            real_quote_reserve: quote_in,
+            real_token_reserve: LAUNCH_CURVE_SUPPLY - tokens_out,
+            graduation_market_cap,
+            complete: true,
+            pool: Pubkey::default(),
+        };
+        assert!(launch.market_cap().unwrap() >= graduation_market_cap);
+        launch
+    }
+
+    // Pool price against the curve's final price, both as quote per token
+    fn assert_at_curve_price(launch: &Launch, quote: u64, tokens: u64) {
+        let pool = quote as u128 * launch.virtual_token_reserve as u128;
+        let curve = tokens as u128 * launch.virtual_quote_reserve as u128;
+        assert!(pool <= curve);
+        // Within a unit of quote
+        assert!(curve - pool < launch.virtual_token_reserve as u128);
+    }
+
+    #[test]
+    fn graduates_at_curve_price_when_supply_runs_short() {
+        // Doubling the virtual quote reserve quadruples the market cap, and the
+        // 500M tokens left on the curve would need to back 250M in the pool
+        let launch = launch_after_buy(4 * VIRTUAL_QUOTE, VIRTUAL_QUOTE);
+        assert_eq!(launch.market_cap().unwrap(), 4 * VIRTUAL_QUOTE);
+
+        let (quote, tokens, excess) = launch.graduation_amounts().unwrap();
+        assert_eq!(tokens, LAUNCH_TOTAL_SUPPLY - LAUNCH_CURVE_SUPPLY);
+        assert_eq!(quote, 24_000_000_000);
+        assert_eq!(quote + excess, launch.real_quote_reserve);
+        assert_at_curve_price(&launch, quote, tokens);
+    }
+
+    #[test]
+    fn graduates_with_the_whole_raise_when_supply_suffices() {
+        let launch = launch_after_buy(VIRTUAL_QUOTE * 3 / 2, VIRTUAL_QUOTE / 4);
+        let (quote, tokens, excess) = launch.graduation_amounts().unwrap();
+        assert_eq!((quote, excess), (launch.real_quote_reserve, 0));
+        assert!(tokens < LAUNCH_TOTAL_SUPPLY - LAUNCH_CURVE_SUPPLY);
+        assert_at_curve_price(&launch, quote, tokens);
+
+        // Buying out the curve leaves enough supply too
+        let launch = launch_after_buy(25 * VIRTUAL_QUOTE, 4 * VIRTUAL_QUOTE);
+        assert_eq!(launch.real_token_reserve, 0);
+        let (quote, tokens, excess) = launch.graduation_amounts().unwrap();
+        assert_eq!((quote, excess), (launch.real_quote_reserve, 0));
+        assert_at_curve_price(&launch, quote, tokens);
+    }
+}
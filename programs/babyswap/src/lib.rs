@@ -4,58 +4,167 @@ use anchor_lang::{
 };
 use anchor_spl::{
     associated_token::*,
+    metadata::{
+        create_metadata_accounts_v3, update_metadata_accounts_v2, 
+        mpl_token_metadata::{types::DataV2, MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH},
+        CreateMetadataAccountsV3, Metadata, MetadataAccount, UpdateMetadataAccountsV2
+    },
     token::{mint_to, Mint, MintTo, Token, TokenAccount, Transfer, burn, Burn, close_account, CloseAccount},
     token::transfer
 };
 use spl_token::state::Account as AccountState;
 
+pub mod concentrated;
+pub mod creator;
+pub mod dca;
+pub mod farm;
+pub mod launch;
+pub mod lock;
+pub mod lp_position;
+pub mod math;
+pub mod migrate;
+pub mod orders;
+pub mod permission;
+pub mod registry;
+pub mod tick_math;
+pub mod twamm;
+use concentrated::*;
+use creator::*;
+use dca::*;
+use farm::*;
+use launch::*;
+use lock::*;
+use lp_position::*;
+use math::*;
+use migrate::*;
+use orders::*;
+use permission::*;
+use registry::*;
+use twamm::*;
+
 declare_id!("4hfWrBXXKKYuQ91bjfAiccq3WTJjWkuYjiwuHK8Xmmmr");
-// const ADMIN_PUBKEY: Pubkey = pubkey!("nktzW8vT4Fzaegd2qqgf24ZPLf11yDVdfEvfbkB4FQz");
+const ADMIN_PUBKEY: Pubkey = pubkey!("nktzW8vT4Fzaegd2qqgf24ZPLf11yDVdfEvfbkB4FQz");
+
+// `PoolInfo` layout written by this program. Pools from before zero-copy have
+// no version; `migrate_pool` converts them to version 1, which keeps the
+// reserves in associated token accounts until `migrate_vaults`.
+pub const POOL_VERSION: u8 = 2;
+// Bytes kept free at the end of `PoolInfo` for fields added by later versions
+pub const POOL_RESERVED_SPACE: usize = 64;
 
 #[program]
 mod babyswap {
     use super::*;
 
-    pub fn initialize_pool(ctx: Context<InitializePool>, fee: u64) -> Result<()> {
+    pub fn initialize_pool(
+        ctx: Context<InitializePool>, 
+        fee: u64, 
+        curve_type: CurveType, 
+        amp: u64,
+        weight_a: u64
+    ) -> Result<()> {
         
         // Fee Should Be 3% Max
         if fee.gt(&300) {
             return Err(ErrorCode::FeeTooHigh.into());
         }
 
+        // StableSwap pools compare raw amounts, so both sides must share decimals
+        if curve_type == CurveType::StableSwap {
+            if !(MIN_AMP..=MAX_AMP).contains(&amp) {
+                return Err(ErrorCode::InvalidAmp.into());
+            }
+            if ctx.accounts.mint_a.decimals != ctx.accounts.mint_b.decimals {
+                return Err(ErrorCode::InvalidDecimals.into());
+            }
+        }
+
+        // Weighted pools need a minimum weight on both sides, e.g. 80/20 or 98/2
+        let weight_a = if curve_type == CurveType::Weighted { weight_a } else { WEIGHT_ONE / 2 };
+        if !(MIN_WEIGHT..=WEIGHT_ONE - MIN_WEIGHT).contains(&weight_a) {
+            return Err(ErrorCode::InvalidWeight.into());
+        }
+
         let mint_a = ctx.accounts.mint_a.key();
         let mint_b = ctx.accounts.mint_b.key();
 
-        // Ensure pool uniqueness
-        let (pool_pda_1, _) = Pubkey::find_program_address(
-            &[
-            b"pool", 
-            mint_b.as_ref()
-            ], 
-            ctx.program_id
-        );
-
-        // Ensure the pool address passed matches the correct order
-        let expected_pool_pda = ctx.accounts.pool.key(); 
-        if expected_pool_pda != pool_pda_1 {
-            return Err(ErrorCode::InvalidTokenOrder.into());
+        // Tokens still selling on a launchpad bonding curve get their pool on graduation
+        if is_launch_mint(&ctx.accounts.mint_a, ctx.program_id) 
+            || is_launch_mint(&ctx.accounts.mint_b, ctx.program_id) {
+            return Err(ErrorCode::LaunchNotGraduated.into());
         }
 
-        let pool = &mut ctx.accounts.pool;
-        pool.pool = expected_pool_pda;
-        pool.mint_a = mint_a;
-        pool.mint_b = mint_b;
-        pool.fees = fee;
-        pool.total_liquidity = 0;
+        let now = Clock::get()?.unix_timestamp;
+        let pool_key = ctx.accounts.pool.key();
+        let lp_mint = ctx.accounts.lp_mint.key();
+        let (weight_b, registry_index) = {
+            let mut pool = ctx.accounts.pool.load_init()?;
+            pool.initialize(pool_key, mint_a, mint_b, lp_mint, fee, now);
+            pool.set_bumps(ctx.bumps.pool, ctx.bumps.lp_mint);
+            pool.set_vaults(
+                ctx.accounts.pool_token_a.key(),
+                ctx.bumps.pool_token_a,
+                ctx.accounts.pool_token_b.key(),
+                ctx.bumps.pool_token_b,
+            );
+
+            let amp = if curve_type == CurveType::StableSwap { amp } else { 0 };
+            pool.curve_type = curve_type as u8;
+            pool.initial_amp = amp;
+            pool.target_amp = amp;
+            pool.weight_a = weight_a;
+            pool.weight_b = WEIGHT_ONE - weight_a;
+            pool.creator = ctx.accounts.user.key();
+            ctx.accounts.registry.append(&mut ctx.accounts.registry_entry, &mut pool);
+            (pool.weight_b, pool.registry_index)
+        };
+
+        let seeds: &[&[u8]] = &[
+            b"pool",
+            mint_b.as_ref(),
+            &[ctx.bumps.pool],
+        ];
+        let signer = &[seeds];
+
+        let symbol_a = token_symbol(&ctx.accounts.mint_a_metadata, &mint_a);
+        let symbol_b = token_symbol(&ctx.accounts.mint_b_metadata, &mint_b);
+        let name = create_lp_metadata(
+            CpiContext::new_with_signer(
+                ctx.accounts.metadata_program.to_account_info(),
+                CreateMetadataAccountsV3 {
+                    metadata: ctx.accounts.lp_mint_metadata.to_account_info(),
+                    mint: ctx.accounts.lp_mint.to_account_info(),
+                    mint_authority: ctx.accounts.pool.to_account_info(),
+                    payer: ctx.accounts.user.to_account_info(),
+                    update_authority: ctx.accounts.pool.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    rent: ctx.accounts.rent.to_account_info(),
+                },
+                signer,
+            ),
+            &symbol_a,
+            &symbol_b
+        )?;
 
-        msg!("Pool Initialized mint_a: {:?}, mint_b: {:?}, fee: {}, pool address: {:?}", 
-        mint_a, mint_b, fee, expected_pool_pda);
+        msg!("Pool Initialized mint_a: {:?}, mint_b: {:?}, fee: {}, curve: {:?}, weights: {}/{}, pool address: {:?}", 
+        mint_a, mint_b, fee, curve_type, weight_a, weight_b, pool_key);
+        msg!("LP mint {:?}: {}", lp_mint, name);
+        msg!("Registered as pool #{}", registry_index);
 
         Ok(())
     }
 
     // 
-    pub fn buy(ctx: Context<BuyInstruction>, swap: BuyInstructionData) -> Result<()> {
+    pub fn buy<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BuyInstruction<'info>>, 
+        swap: BuyInstructionData
+    ) -> Result<()> {
+        permission::check_access(
+            &*ctx.accounts.pool.load()?,
+            &ctx.accounts.user.key(),
+            &ctx.accounts.allowlist_entry,
+            &ctx.accounts.gate_token,
+        )?;
 
         // Validate slippage
         let slippage = swap.slippage;
@@ -83,17 +192,29 @@ mod babyswap {
             return Err(ErrorCode::InsufficientTokenBBalance.into());
         }
 
-        let pool = &mut ctx.accounts.pool;
-        let _mint_a = pool.mint_a;
+        // Long-term orders trade first, at the pre-swap price
+        twamm::settle_if_needed(
+            &ctx.accounts.pool,
+            (&mut ctx.accounts.pool_token_a_ata, &mut ctx.accounts.pool_token_b_ata),
+            &ctx.accounts.twamm,
+            (&mut ctx.accounts.twamm_vault_a, &mut ctx.accounts.twamm_vault_b),
+            &ctx.accounts.token_program,
+        )?;
+
+        let pool = ctx.accounts.pool.load()?;
+        let mint_a = pool.mint_a;
         let mint_b = pool.mint_b;
+        let bump = pool.bump;
 
         // Input and output reserves for buy operation
         let output_reserve = ctx.accounts.pool_token_b_ata.amount as u128;
         let input_reserve = ctx.accounts.pool_token_a_ata.amount as u128;
 
         // Calculate fee for buy operation
+        let now = Clock::get()?.unix_timestamp;
+        let fee_rate = pool.current_fee(now);
         let fee_amount = amount
-            .checked_mul(pool.fees as u128)  
+            .checked_mul(fee_rate as u128)  
             .and_then(|v| v.checked_div(10000))
             .ok_or(ErrorCode::IntegerOverflowFeeAmount)?;
         
@@ -101,22 +222,14 @@ mod babyswap {
             .checked_sub(fee_amount)
             .ok_or(ErrorCode::IntegerOverflowNetInput)?;
 
-        // Update reserves and calculate output
-        let new_input_reserve = input_reserve
-            .checked_add(net_input)
-            .ok_or(ErrorCode::IntegerOverflowNewInputReserve)?;
-
-        let constant_product = input_reserve
-            .checked_mul(output_reserve)
-            .ok_or(ErrorCode::IntegerOverflowK)?;
-
-        let new_output_reserve = constant_product
-            .checked_div(new_input_reserve)
-            .ok_or(ErrorCode::IntegerOverflowNewOutputReserve)?;
-
-        let gross_output_amount = output_reserve
-            .checked_sub(new_output_reserve)
-            .ok_or(ErrorCode::InsufficientLiquidity)?;
+        // Calculate output along the pool's curve
+        let gross_output_amount = pool.swap_output(
+            input_reserve, 
+            output_reserve, 
+            net_input, 
+            true,
+            now
+        )?;
 
         // Validate slippage
         let slippage_value = gross_output_amount
@@ -132,15 +245,38 @@ mod babyswap {
         if gross_output_amount.lt(&min_output_amount) {
             return Err(ErrorCode::InsufficientOutputAmount.into());
         }
+        // Creator's share of the fee, paid into the creator fee vault
+        let creator_fee = match (pool.creator_fee_bps, &ctx.accounts.config) {
+            (0, _) => 0,
+            (_, Some(config)) => pool.creator_fee(fee_amount, config.max_creator_fee_bps),
+            (_, None) => return Err(ErrorCode::MissingConfig.into()),
+        };
+        if creator_fee > 0 && ctx.accounts.creator_fee_vault.is_none() {
+            return Err(ErrorCode::MissingCreatorFeeVault.into());
+        }
+        // The pool account is passed to the CPIs below as the vault authority
+        drop(pool);
 
         // Prepare signer seeds
         let seeds: &[&[u8]] = &[
             b"pool",
             mint_b.as_ref(),
-            &[ctx.bumps.pool],
+            &[bump],
         ];
         let signer = &[seeds];
 
+        // Optional integrator share of the input fee
+        let (referral_fee, referrer) = referral_fee(
+            &ctx.accounts.config,
+            ctx.remaining_accounts,
+            swap.referral_bps,
+            fee_amount,
+            mint_a
+        )?;
+        let retained_fee = fee_amount
+            .checked_sub(referral_fee + creator_fee)
+            .ok_or(ErrorCode::IntegerOverflowFeeAmount)?;
+
         // Transfer Token A from user to pool
         let transfer_to_pool = spl_token::instruction::transfer(
             &ctx.accounts.token_program.key(),
@@ -148,7 +284,7 @@ mod babyswap {
             &ctx.accounts.pool_token_a_ata.key(),
             &ctx.accounts.user.key(),
             &[],
-            amount as u64
+            (amount - referral_fee - creator_fee) as u64
         )?;
 
         invoke_signed(
@@ -162,6 +298,57 @@ mod babyswap {
             signer
         )?;
 
+        // Transfer the referral fee from user to referrer
+        if let Some(referrer) = referrer {
+            let transfer_to_referrer = spl_token::instruction::transfer(
+                &ctx.accounts.token_program.key(),
+                &ctx.accounts.user_token_a_ata.key(),
+                referrer.key,
+                &ctx.accounts.user.key(),
+                &[],
+                referral_fee as u64
+            )?;
+
+            invoke(
+                &transfer_to_referrer,
+                &[
+                    ctx.accounts.token_program.to_account_info().clone(),
+                    ctx.accounts.user_token_a_ata.to_account_info().clone(),
+                    referrer.clone(),
+                    ctx.accounts.user.to_account_info().clone(),
+                ],
+            )?;
+
+            emit!(ReferralFeePaid {
+                pool: ctx.accounts.pool.key(),
+                referrer: referrer.key(),
+                mint: mint_a,
+                amount: referral_fee as u64,
+            });
+        }
+
+        // Transfer the creator fee from user to the creator fee vault
+        if let Some(creator_fee_vault) = ctx.accounts.creator_fee_vault.as_ref().filter(|_| creator_fee > 0) {
+            let transfer_to_creator = spl_token::instruction::transfer(
+                &ctx.accounts.token_program.key(),
+                &ctx.accounts.user_token_a_ata.key(),
+                &creator_fee_vault.key(),
+                &ctx.accounts.user.key(),
+                &[],
+                creator_fee as u64
+            )?;
+
+            invoke(
+                &transfer_to_creator,
+                &[
+                    ctx.accounts.token_program.to_account_info().clone(),
+                    ctx.accounts.user_token_a_ata.to_account_info().clone(),
+                    creator_fee_vault.to_account_info().clone(),
+                    ctx.accounts.user.to_account_info().clone(),
+                ],
+            )?;
+        }
+
         // Transfer Token B from pool to user
         let transfer_tokens = spl_token::instruction::transfer(
             &ctx.accounts.token_program.key(),
@@ -183,41 +370,35 @@ mod babyswap {
             signer,
         )?;
 
-        // Constant product invariant check
-        let simulated_input_reserve = input_reserve
-            .checked_add(net_input) 
-            .ok_or(ErrorCode::IntegerOverflow11)?;
-
-        let simulated_output_reserve = output_reserve
-            .checked_sub(gross_output_amount) 
-            .ok_or(ErrorCode::IntegerOverflow11)?;
+        // Invariant check against the vault balances actually left after the transfers
+        ctx.accounts.pool_token_a_ata.reload()?;
+        ctx.accounts.pool_token_b_ata.reload()?;
+        let balance_in = ctx.accounts.pool_token_a_ata.amount as u128;
+        let balance_out = ctx.accounts.pool_token_b_ata.amount as u128;
 
-        let k_initial = input_reserve
-            .checked_mul(output_reserve)
+        // The retained input fee stays in the vault but is not part of the curve
+        let adjusted_balance_in = balance_in
+            .checked_sub(retained_fee)
             .ok_or(ErrorCode::IntegerOverflow11)?;
 
-        let k_final = simulated_input_reserve
-            .checked_mul(simulated_output_reserve)
-            .ok_or(ErrorCode::IntegerOverflow12)?;
-
-        let difference = k_initial.saturating_sub(k_final);
+        let mut pool = ctx.accounts.pool.load_mut()?;
+        pool.check_invariant(
+            (input_reserve, output_reserve),
+            (adjusted_balance_in, balance_out),
+            true,
+            now
+        )?;
 
-        const MAX_DEVIATION_BPS: u128 = 1; // 0.001% || 0.1 BPS
-        let max_allowed_difference = k_initial
-            .checked_mul(MAX_DEVIATION_BPS)
-            .and_then(|v| v.checked_div(100000))
-            .ok_or(ErrorCode::IntegerOverflow12)?;
+        pool.check_circuit_breaker(
+            (input_reserve, output_reserve),
+            (balance_in, balance_out),
+            now,
+            Clock::get()?.slot
+        )?;
 
-        if difference > max_allowed_difference {
-            msg!(
-                "Constant product violated beyond tolerance: k_initial={}, k_final={}, difference={}, max_allowed={}",
-                k_initial,
-                k_final,
-                difference,
-                max_allowed_difference
-            );
-            return Err(ErrorCode::ConstantProductInvariantViolated.into());
-        }
+        // Feed the post-trade price into the volatility tracker
+        pool.record_price(balance_in, balance_out, now);
+        pool.record_trade(amount, gross_output_amount, fee_amount, now);
 
         msg!(
             "User bought {} tokens for {} tokens with slippage tolerance {:.2}%",
@@ -229,7 +410,16 @@ mod babyswap {
         Ok(())
     }
     
-    pub fn sell(ctx: Context<SellInstruction>, swap: SellInstructionData) -> Result<()> {
+    pub fn sell<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SellInstruction<'info>>, 
+        swap: SellInstructionData
+    ) -> Result<()> {
+        permission::check_access(
+            &*ctx.accounts.pool.load()?,
+            &ctx.accounts.user.key(),
+            &ctx.accounts.allowlist_entry,
+            &ctx.accounts.gate_token,
+        )?;
 
         // Validate slippage
         let slippage = swap.slippage;
@@ -257,9 +447,20 @@ mod babyswap {
             return Err(ErrorCode::InsufficientTokenABalance.into());
         }
 
-        let pool = &mut ctx.accounts.pool;
-        // let mint_a = pool.mint_a;
+        // Long-term orders trade first, at the pre-swap price. The sell
+        // context names the pool's vaults the other way round.
+        twamm::settle_if_needed(
+            &ctx.accounts.pool,
+            (&mut ctx.accounts.pool_token_b_ata, &mut ctx.accounts.pool_token_a_ata),
+            &ctx.accounts.twamm,
+            (&mut ctx.accounts.twamm_vault_a, &mut ctx.accounts.twamm_vault_b),
+            &ctx.accounts.token_program,
+        )?;
+
+        let pool = ctx.accounts.pool.load()?;
+        let mint_a = pool.mint_a;
         let mint_b = pool.mint_b;
+        let bump = pool.bump;
 
         // Input and output reserves for sell operation
         let input_reserve = ctx.accounts.pool_token_a_ata.amount as u128;
@@ -267,27 +468,21 @@ mod babyswap {
 
         // No fee deduction during input for sell operation
         let net_input = amount;
-
-        // Update reserves and calculate output
-        let new_input_reserve = input_reserve
-            .checked_add(net_input)
-            .ok_or(ErrorCode::IntegerOverflowNewInputReserve)?;
-
-        let constant_product = input_reserve
-            .checked_mul(output_reserve)
-            .ok_or(ErrorCode::IntegerOverflowK)?;
-
-        let new_output_reserve = constant_product
-            .checked_div(new_input_reserve)
-            .ok_or(ErrorCode::IntegerOverflowNewOutputReserve)?;
-
-        let gross_output_amount = output_reserve
-            .checked_sub(new_output_reserve)
-            .ok_or(ErrorCode::InsufficientLiquidity)?;
+        let now = Clock::get()?.unix_timestamp;
+        let fee_rate = pool.current_fee(now);
+
+        // Calculate output along the pool's curve
+        let gross_output_amount = pool.swap_output(
+            input_reserve, 
+            output_reserve, 
+            net_input, 
+            false,
+            now
+        )?;
 
         // Calculate fee for sell operation
         let fee_amount = gross_output_amount
-            .checked_mul(pool.fees as u128)
+            .checked_mul(fee_rate as u128)
             .and_then(|v| v.checked_div(10000))
             .ok_or(ErrorCode::IntegerOverflowFeeAmount)?;
 
@@ -309,13 +504,24 @@ mod babyswap {
         if net_output_amount.lt(&min_output_amount) {
             return Err(ErrorCode::InsufficientOutputAmount.into());
         }
+        // Creator's share of the fee, paid into the creator fee vault
+        let creator_fee = match (pool.creator_fee_bps, &ctx.accounts.config) {
+            (0, _) => 0,
+            (_, Some(config)) => pool.creator_fee(fee_amount, config.max_creator_fee_bps),
+            (_, None) => return Err(ErrorCode::MissingConfig.into()),
+        };
+        if creator_fee > 0 && ctx.accounts.creator_fee_vault.is_none() {
+            return Err(ErrorCode::MissingCreatorFeeVault.into());
+        }
+        // The pool account is passed to the CPIs below as the vault authority
+        drop(pool);
 
         // Prepare signer seeds
         let seeds: &[&[u8]] = &[
             b"pool",
             
             mint_b.as_ref(),
-            &[ctx.bumps.pool],
+            &[bump],
         ];
         let signer = &[seeds];
 
@@ -361,41 +567,100 @@ mod babyswap {
             signer,
         )?;
 
-        // Constant product invariant check
-        let simulated_input_reserve = input_reserve
-            .checked_add(net_input) 
-            .ok_or(ErrorCode::IntegerOverflow11)?;
+        // Pay the integrator's share of the output fee out of the vault
+        let (referral_fee, referrer) = referral_fee(
+            &ctx.accounts.config,
+            ctx.remaining_accounts,
+            swap.referral_bps,
+            fee_amount,
+            mint_a
+        )?;
 
-        let simulated_output_reserve = output_reserve
-            .checked_sub(net_output_amount) 
-            .ok_or(ErrorCode::IntegerOverflow11)?;
+        if let Some(referrer) = referrer {
+            let transfer_to_referrer = spl_token::instruction::transfer(
+                &ctx.accounts.token_program.key(),
+                &ctx.accounts.pool_token_b_ata.key(),
+                referrer.key,
+                &ctx.accounts.pool.key(),
+                &[],
+                referral_fee as u64
+            )?;
+
+            invoke_signed(
+                &transfer_to_referrer,
+                &[
+                    ctx.accounts.token_program.to_account_info().clone(),
+                    ctx.accounts.pool_token_b_ata.to_account_info().clone(),
+                    referrer.clone(),
+                    ctx.accounts.pool.to_account_info().clone(),
+                ],
+                signer,
+            )?;
+
+            emit!(ReferralFeePaid {
+                pool: ctx.accounts.pool.key(),
+                referrer: referrer.key(),
+                mint: mint_a,
+                amount: referral_fee as u64,
+            });
+        }
 
-        let k_initial = input_reserve
-            .checked_mul(output_reserve)
-            .ok_or(ErrorCode::IntegerOverflow11)?;
+        let retained_fee = fee_amount
+            .checked_sub(referral_fee + creator_fee)
+            .ok_or(ErrorCode::IntegerOverflowFeeAmount)?;
+
+        // Pay the creator's share of the output fee out of the vault
+        if let Some(creator_fee_vault) = ctx.accounts.creator_fee_vault.as_ref().filter(|_| creator_fee > 0) {
+            let transfer_to_creator = spl_token::instruction::transfer(
+                &ctx.accounts.token_program.key(),
+                &ctx.accounts.pool_token_b_ata.key(),
+                &creator_fee_vault.key(),
+                &ctx.accounts.pool.key(),
+                &[],
+                creator_fee as u64
+            )?;
+
+            invoke_signed(
+                &transfer_to_creator,
+                &[
+                    ctx.accounts.token_program.to_account_info().clone(),
+                    ctx.accounts.pool_token_b_ata.to_account_info().clone(),
+                    creator_fee_vault.to_account_info().clone(),
+                    ctx.accounts.pool.to_account_info().clone(),
+                ],
+                signer,
+            )?;
+        }
 
-        let k_final = simulated_input_reserve
-            .checked_mul(simulated_output_reserve)
-            .ok_or(ErrorCode::IntegerOverflow12)?;
+        // Invariant check against the vault balances actually left after the transfers
+        ctx.accounts.pool_token_a_ata.reload()?;
+        ctx.accounts.pool_token_b_ata.reload()?;
+        let balance_in = ctx.accounts.pool_token_a_ata.amount as u128;
+        let balance_out = ctx.accounts.pool_token_b_ata.amount as u128;
 
-        let difference = k_initial.saturating_sub(k_final);
+        // The retained output fee stays in the vault but is not part of the curve
+        let adjusted_balance_out = balance_out
+            .checked_sub(retained_fee)
+            .ok_or(ErrorCode::IntegerOverflow11)?;
 
-        const MAX_DEVIATION_BPS: u128 = 1; // 0.001% || 0.1 BPS
-        let max_allowed_difference = k_initial
-            .checked_mul(MAX_DEVIATION_BPS)
-            .and_then(|v| v.checked_div(100000))
-            .ok_or(ErrorCode::IntegerOverflow12)?;
+        let mut pool = ctx.accounts.pool.load_mut()?;
+        pool.check_invariant(
+            (input_reserve, output_reserve),
+            (balance_in, adjusted_balance_out),
+            false,
+            now
+        )?;
 
-        if difference > max_allowed_difference {
-            msg!(
-                "Constant product violated beyond tolerance: k_initial={}, k_final={}, difference={}, max_allowed={}",
-                k_initial,
-                k_final,
-                difference,
-                max_allowed_difference
-            );
-            return Err(ErrorCode::ConstantProductInvariantViolated.into());
-        }
+        pool.check_circuit_breaker(
+            (output_reserve, input_reserve),
+            (balance_out, balance_in),
+            now,
+            Clock::get()?.slot
+        )?;
+
+        // Feed the post-trade price into the volatility tracker
+        pool.record_price(balance_out, balance_in, now);
+        pool.record_trade(gross_output_amount, amount, fee_amount, now);
 
         msg!(
             "User sold {} tokens for {} tokens with slippage tolerance {:.2}%",
@@ -409,10 +674,22 @@ mod babyswap {
 
     // 
     pub fn add_liquidity(ctx: Context<AddLiquidity>,  amount_one: u64, amount_two: u64) -> Result<()> {
+        permission::check_access(
+            &*ctx.accounts.pool.load()?,
+            &ctx.accounts.user.key(),
+            &ctx.accounts.allowlist_entry,
+            &ctx.accounts.gate_token,
+        )?;
         // you have to create checks for some of these params like fee
-        let pool = &mut ctx.accounts.pool;
-       
-        let total_liquidity = pool.total_liquidity as u128;
+        // Deposit at the price long-term orders have moved the pool to
+        twamm::settle_if_needed(
+            &ctx.accounts.pool,
+            (&mut ctx.accounts.pool_token_a, &mut ctx.accounts.pool_token_b),
+            &ctx.accounts.twamm,
+            (&mut ctx.accounts.twamm_vault_a, &mut ctx.accounts.twamm_vault_b),
+            &ctx.accounts.token_program,
+        )?;
+
         let mint_a = ctx.accounts.mint_a.key();
         let mint_b = ctx.accounts.mint_b.key();
 
@@ -420,33 +697,9 @@ mod babyswap {
         let reserve_a = ctx.accounts.pool_token_a.amount as u128;
         let reserve_b = ctx.accounts.pool_token_b.amount as u128;
 
-        let amount_a = amount_one as u128;
-        let amount_b = amount_two as u128;
-
-        // Check if we're maintaining pool ratios based on existing reserves
-        let (required_amount_a, required_amount_b) = if total_liquidity > 0 {
-            // Ensure that reserve_a is not zero to avoid division by zero
-            if reserve_a == 0 {
-                return Err(ErrorCode::ZeroReserve.into());
-            }
-
-            // Calculate `required_amount_b` based on `amount_a` to maintain pool ratio
-            let balanced_amount_b = amount_a
-                .checked_mul(reserve_b)
-                .and_then(|result| result.checked_div(reserve_a))
-                .ok_or(ErrorCode::Overflow)?;
-
-            let require_b_amount = balanced_amount_b as u64;
-
-            if amount_b < balanced_amount_b {
-                // Not enough `Token B` provided to maintain the ratio
-                return Err(ErrorCode::InsufficientTokenB.into());
-            }
-
-            (amount_a as u64, require_b_amount)
-        } else {
-            // This is the initial liquidity, so use `amount_a` and `amount_b` directly
-            (amount_a as u64, amount_b as u64)
+        let (bump, (required_amount_a, required_amount_b, lp_amount)) = {
+            let pool = ctx.accounts.pool.load()?;
+            (pool.bump, pool.deposit_amounts(reserve_a, reserve_b, amount_one as u128, amount_two as u128)?)
         };
 
         let user_balance_a = ctx.accounts.user_token_a.amount;
@@ -459,7 +712,7 @@ mod babyswap {
         let seeds: &[&[u8]] = &[
             b"pool",
             mint_b.as_ref(),
-            &[ctx.bumps.pool],
+            &[bump],
         ];
         let signer = &[seeds];
 
@@ -485,36 +738,12 @@ mod babyswap {
         );
         transfer(token_b_to_pool, required_amount_b)?;
 
-        // Calculate the LP tokens to mint for the user based on their contribution
-        let lp_amount;
-        if reserve_a == 0 && reserve_b == 0 {
-            // Initialize the pool by minting LP tokens equal to `amount_a`
-            lp_amount = amount_a;
-        } else {
-            let total_lp_supply_u128 = total_liquidity;
-
-            // Calculate LP tokens for `amount_a`
-            let lp_tokens_for_amount_a = amount_a
-                .checked_mul(total_lp_supply_u128)
-                .and_then(|res| res.checked_div(reserve_a))
-                .ok_or(ErrorCode::Overflow)?;
-
-            // Calculate LP tokens for `amount_b`
-            let lp_tokens_for_amount_b = amount_b
-                .checked_mul(total_lp_supply_u128)
-                .and_then(|res| res.checked_div(reserve_b))
-                .ok_or(ErrorCode::Overflow)?;
-
-            // Take the minimum of both calculated LP tokens to maintain balance
-            lp_amount = std::cmp::min(lp_tokens_for_amount_a, lp_tokens_for_amount_b);
-        }
-
         // Mint LP tokens to user's LP token account
         mint_to(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 MintTo {
-                    authority: pool.to_account_info(),
+                    authority: ctx.accounts.pool.to_account_info(),
                     to: ctx.accounts.user_lp_token_account.to_account_info(),
                     mint: ctx.accounts.lp_mint.to_account_info(),
                 },
@@ -524,11 +753,23 @@ mod babyswap {
         )?;
 
         // Update pool state: increase reserves and total liquidity
+        let mut pool = ctx.accounts.pool.load_mut()?;
         pool.total_liquidity = pool
             .total_liquidity
             .checked_add(lp_amount)
             .ok_or(ErrorCode::Overflow)?;
-        pool.lp_mint = ctx.accounts.lp_mint.key();
+
+        // Cost basis for the owner's position, if tracked
+        if let Some(position) = ctx.accounts.lp_position.as_mut() {
+            let price = pool
+                .spot_price(
+                    reserve_a + required_amount_a as u128,
+                    reserve_b + required_amount_b as u128,
+                    Clock::get()?.unix_timestamp,
+                )
+                .ok_or(ErrorCode::ZeroReserve)?;
+            position.record_deposit(required_amount_a, required_amount_b, lp_amount as u64, price);
+        }
 
         msg!(
             "Add Liquidity: {} Tokens Of {} Token A and {} Tokens Of {} Token B", 
@@ -540,15 +781,23 @@ mod babyswap {
 
     // 
     pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, liquidity: u64) -> Result<()> {
-        let pool = &mut ctx.accounts.pool;
+        // Withdraw at the price long-term orders have moved the pool to
+        twamm::settle_if_needed(
+            &ctx.accounts.pool,
+            (&mut ctx.accounts.pool_token_a, &mut ctx.accounts.pool_token_b),
+            &ctx.accounts.twamm,
+            (&mut ctx.accounts.twamm_vault_a, &mut ctx.accounts.twamm_vault_b),
+            &ctx.accounts.token_program,
+        )?;
+
         let lp_amount = liquidity as u128;
-        let total_liquidity = pool.total_liquidity as u128;
-        let _mint_a = pool.mint_a.key();
-        let mint_b = pool.mint_b.key();
+        let (total_liquidity, mint_b, bump) = {
+            let pool = ctx.accounts.pool.load()?;
+            (pool.total_liquidity, pool.mint_b, pool.bump)
+        };
 
         // Verify non-zero amounts
         require!(lp_amount > 0, ErrorCode::InvalidAmount);
-        require!(total_liquidity > 0, ErrorCode::InsufficientLiquidity);
         
         // Verify user has enough LP tokens
         require!(
@@ -560,18 +809,12 @@ mod babyswap {
         let pool_token_a_amount = ctx.accounts.pool_token_a.amount as u128;
         let pool_token_b_amount = ctx.accounts.pool_token_b.amount as u128;
 
-        let amount_a = lp_amount
-            .checked_mul(pool_token_a_amount)
-            .ok_or(ErrorCode::OverflowRemoveLiquidityA)?
-            .checked_div(total_liquidity)
-            .ok_or(ErrorCode::OverflowRemoveLiquidityA)?;
-            
-        let amount_b = lp_amount
-            .checked_mul(pool_token_b_amount)
-            .ok_or(ErrorCode::OverflowRemoveLiquidityB)?
-            .checked_div(total_liquidity)
-            .ok_or(ErrorCode::OverflowRemoveLiquidityB)?;
-           
+        let (amount_a, amount_b) = ctx.accounts.pool.load()?.withdraw_amounts(
+            pool_token_a_amount,
+            pool_token_b_amount,
+            lp_amount,
+        )?;
+
         // Verify calculated amounts are non-zero
         require!(amount_a > 0, ErrorCode::InvalidCalculatedAmount);
         require!(amount_b > 0, ErrorCode::InvalidCalculatedAmount);
@@ -580,7 +823,7 @@ mod babyswap {
         let seeds: &[&[u8]] = &[
             b"pool",
             mint_b.as_ref(),
-            &[ctx.bumps.pool],
+            &[bump],
         ];
         let signer = &[seeds];
 
@@ -600,7 +843,7 @@ mod babyswap {
             Transfer {
                 from: ctx.accounts.pool_token_a.to_account_info(),
                 to: ctx.accounts.user_token_a.to_account_info(),
-                authority: pool.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
             },
             signer,
         );
@@ -612,7 +855,7 @@ mod babyswap {
             Transfer {
                 from: ctx.accounts.pool_token_b.to_account_info(),
                 to: ctx.accounts.user_token_b.to_account_info(),
-                authority: pool.to_account_info(),
+                authority: ctx.accounts.pool.to_account_info(),
             },
             signer,
         );
@@ -630,9 +873,12 @@ mod babyswap {
         burn(burn_ctx, liquidity)?;
 
         // Update pool state: decrease total liquidity
-        pool.total_liquidity = total_liquidity
+        ctx.accounts.pool.load_mut()?.total_liquidity = total_liquidity
             .checked_sub(lp_amount)
             .ok_or(ErrorCode::Overflow)?;
+        if let Some(position) = ctx.accounts.lp_position.as_mut() {
+            position.record_withdrawal(liquidity);
+        }
 
         // Optional: Close user's LP token account if they have removed all their liquidity
         if ctx.accounts.user_lp_token_account.amount == 0 {
@@ -652,135 +898,1528 @@ mod babyswap {
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct RemoveLiquidity<'info> {
-    #[account(
-        mut,
-        has_one = lp_mint @ ErrorCode::InvalidLPMint,
-        seeds = [
-            b"pool", 
-            mint_b.key().as_ref()
-        ],
-        bump,
-        constraint = pool_token_a.mint == pool.mint_a @ ErrorCode::InvalidPoolTokenA,
-        constraint = pool_token_b.mint == pool.mint_b @ ErrorCode::InvalidPoolTokenB
-    )]
-    pub pool: Account<'info, PoolInfo>,
+    // Close a pool with no liquidity left. The vaults and pool account are
+    // closed, rent goes back to the creator and the pool leaves the registry.
+    pub fn close_pool(ctx: Context<ClosePool>) -> Result<()> {
+        let (pool_key, mint_b, bump, creator_fee_vault_bump, registered) = {
+            let pool = ctx.accounts.pool.load()?;
+            if pool.total_liquidity != 0 || pool.long_term_orders != 0 {
+                return Err(ErrorCode::PoolNotEmpty.into());
+            }
+            (pool.pool, pool.mint_b, pool.bump, pool.creator_fee_vault_bump, pool.registered != 0)
+        };
+        // Creator fees have to be claimed before the creator fee vault closes
+        if creator_fee_vault_bump != 0 && ctx.accounts.creator_fee_vault.is_none() {
+            return Err(ErrorCode::MissingCreatorFeeVault.into());
+        }
+        let vaults = [
+            Some(&ctx.accounts.vault_a),
+            Some(&ctx.accounts.vault_b),
+            ctx.accounts.creator_fee_vault.as_ref(),
+        ];
+        if vaults.iter().flatten().any(|vault| vault.amount != 0) {
+            return Err(ErrorCode::PoolNotEmpty.into());
+        }
 
-    #[account(mut)]
-    pub mint_a: Box<Account<'info, Mint>>,
-    #[account(mut)]
-    pub mint_b: Box<Account<'info, Mint>>,
+        let seeds: &[&[u8]] = &[
+            b"pool",
+            mint_b.as_ref(),
+            &[bump],
+        ];
+        let signer = &[seeds];
 
-    #[account(
-        mut,
-        constraint = pool_token_a.owner == pool.to_account_info().key() @ ErrorCode::InvalidPoolTokenAOwner
-    )]
-    pub pool_token_a: Account<'info, TokenAccount>,
+        for vault in vaults.into_iter().flatten() {
+            close_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: vault.to_account_info(),
+                    destination: ctx.accounts.rent_receiver.to_account_info(),
+                    authority: ctx.accounts.pool.to_account_info(),
+                },
+                signer,
+            ))?;
+        }
 
-    #[account(
-        mut,
-        constraint = pool_token_b.owner == pool.to_account_info().key() @ ErrorCode::InvalidPoolTokenBOwner
-    )]
-    pub pool_token_b: Account<'info, TokenAccount>,
+        let accounts = &mut *ctx.accounts;
+        if registered {
+            let registry_entry = accounts
+                .registry_entry
+                .as_mut()
+                .filter(|entry| entry.pool == pool_key)
+                .ok_or(ErrorCode::InvalidRegistryEntry)?;
+            let rent_receiver = accounts.rent_receiver.to_account_info();
+            match (&accounts.last_registry_entry, &accounts.last_pool) {
+                _ if registry_entry.index + 1 == accounts.registry.pool_count => {
+                    accounts.registry.pool_count -= 1;
+                    registry_entry.close(rent_receiver)?;
+                }
+                (Some(last_entry), Some(last_pool)) => {
+                    accounts.registry.swap_remove(
+                        registry_entry,
+                        last_entry,
+                        &mut *last_pool.load_mut()?,
+                    )?;
+                    last_entry.close(rent_receiver)?;
+                }
+                _ => return Err(ErrorCode::InvalidRegistryEntry.into()),
+            }
+        }
 
-    #[account(mut)]
-    pub lp_mint: Account<'info, Mint>,
+        msg!("Pool {:?} closed, rent returned to {:?}", pool_key, accounts.rent_receiver.key());
 
-    #[account(
-        mut,
-        constraint = user_token_a.owner == user.key() @ ErrorCode::InvalidUserTokenA,
-        constraint = user_token_a.mint == pool.mint_a @ ErrorCode::InvalidUserTokenAMint
-    )]
-    pub user_token_a: Account<'info, TokenAccount>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        constraint = user_token_b.owner == user.key() @ ErrorCode::InvalidUserTokenB,
-        constraint = user_token_b.mint == pool.mint_b @ ErrorCode::InvalidUserTokenBMint
-    )]
-    pub user_token_b: Account<'info, TokenAccount>,
+    // Start ramping a StableSwap pool's amplification coefficient towards `target_amp`
+    pub fn ramp_amp(ctx: Context<RampAmp>, target_amp: u64, stop_ramp_ts: i64) -> Result<()> {
+        let mut pool = ctx.accounts.pool.load_mut()?;
+        let now = Clock::get()?.unix_timestamp;
 
-    #[account(
-        mut,
-        constraint = user_lp_token_account.owner == user.key() @ ErrorCode::InvalidUserLPOwner,
-        constraint = user_lp_token_account.mint == lp_mint.key() @ ErrorCode::InvalidUserLPMint
-    )]
-    pub user_lp_token_account: Account<'info, TokenAccount>,
+        // Only one ramp at a time
+        if now < pool.ramp_stop_ts {
+            return Err(ErrorCode::RampInProgress.into());
+        }
+        if stop_ramp_ts < now.saturating_add(MIN_RAMP_DURATION) {
+            return Err(ErrorCode::RampTooShort.into());
+        }
+        if !(MIN_AMP..=MAX_AMP).contains(&target_amp) {
+            return Err(ErrorCode::InvalidAmp.into());
+        }
 
-    #[account(mut)]
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>
-}
+        let current = pool.amp(now);
+        let too_high = target_amp > current.saturating_mul(MAX_AMP_CHANGE);
+        let too_low = target_amp.saturating_mul(MAX_AMP_CHANGE) < current;
+        if too_high || too_low {
+            return Err(ErrorCode::AmpChangeTooLarge.into());
+        }
 
-#[account]
-pub struct PoolInfo {
-    pub pool: Pubkey,
-    pub mint_a: Pubkey,
-    pub mint_b: Pubkey,
-    pub lp_mint: Pubkey,
-    pub fees: u64,
-    pub total_liquidity: u128
-}
+        pool.initial_amp = current;
+        pool.target_amp = target_amp;
+        pool.ramp_start_ts = now;
+        pool.ramp_stop_ts = stop_ramp_ts;
 
-#[derive(Accounts)]
-pub struct InitializePool<'info> {
-    #[account(
-        init,
-        seeds = [
-            b"pool",
-            mint_b.key().as_ref()
-        ],
-        bump,
-        payer = user,
-        space = 8 + std::mem::size_of::<PoolInfo>()
-    )]
-    pub pool: Box<Account<'info, PoolInfo>>,
+        msg!("Ramping A from {} to {} until {}", current, target_amp, stop_ramp_ts);
 
-    #[account(constraint = mint_a.key() != mint_b.key() @ ErrorCode::SameTokenPool)]
-    pub mint_a: Box<Account<'info, Mint>>, // ADD SOME BOXING HERE
-    pub mint_b: Box<Account<'info, Mint>>,
+        Ok(())
+    }
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        associated_token::mint = mint_a,
-        associated_token::authority = pool,
-    )]
-    pub pool_token_a: Box<Account<'info, TokenAccount>>,
-    #[account(
-        init_if_needed,
-        payer = user,
-        associated_token::mint = mint_b,
-        associated_token::authority = pool,
-    )]
-    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+    // Freeze the amplification coefficient at its current value
+    pub fn stop_ramp_amp(ctx: Context<RampAmp>) -> Result<()> {
+        let mut pool = ctx.accounts.pool.load_mut()?;
+        let now = Clock::get()?.unix_timestamp;
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+        let current = pool.amp(now);
+        pool.initial_amp = current;
+        pool.target_amp = current;
+        pool.ramp_start_ts = now;
+        pool.ramp_stop_ts = now;
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>
-}
+        msg!("Stopped ramping A at {}", current);
 
-#[derive(Accounts)]
-pub struct AddLiquidity<'info> {
-    #[account(
+        Ok(())
+    }
+
+    // Turn volatility-based fees on or off. The base fee stays `pool.fees`.
+    pub fn set_dynamic_fee(
+        ctx: Context<SetDynamicFee>, 
+        enabled: bool, 
+        max_fee: u64, 
+        volatility_multiplier: u64, 
+        volatility_half_life: i64
+    ) -> Result<()> {
+        let mut pool = ctx.accounts.pool.load_mut()?;
+
+        // Fee Should Be 3% Max
+        if max_fee.gt(&300) {
+            return Err(ErrorCode::FeeTooHigh.into());
+        }
+        if enabled && (max_fee < pool.fees || volatility_half_life <= 0) {
+            return Err(ErrorCode::InvalidDynamicFee.into());
+        }
+
+        pool.dynamic_fee = enabled as u8;
+        pool.max_fee = max_fee;
+        pool.volatility_multiplier = volatility_multiplier;
+        pool.volatility_half_life = volatility_half_life;
+
+        msg!(
+            "Dynamic fee {}: base fee {}, max fee {}, multiplier {}, half life {}s",
+            if enabled { "enabled" } else { "disabled" },
+            pool.fees, max_fee, volatility_multiplier, volatility_half_life
+        );
+
+        Ok(())
+    }
+
+    // Price-impact limits per swap and per window, 0 disables a limit
+    pub fn set_circuit_breaker(
+        ctx: Context<SetCircuitBreaker>, 
+        max_price_impact_bps: u64, 
+        max_window_movement_bps: u64, 
+        breaker_window: i64
+    ) -> Result<()> {
+        if max_price_impact_bps > 10000 || max_window_movement_bps > 10000 || breaker_window < 0 {
+            return Err(ErrorCode::InvalidCircuitBreaker.into());
+        }
+
+        let mut pool = ctx.accounts.pool.load_mut()?;
+        pool.max_price_impact_bps = max_price_impact_bps;
+        pool.max_window_movement_bps = max_window_movement_bps;
+        pool.breaker_window = breaker_window;
+        // Start a fresh window under the new settings
+        pool.breaker_window_price = 0;
+
+        msg!(
+            "Circuit breaker: {} bps per swap, {} bps per {}",
+            max_price_impact_bps,
+            max_window_movement_bps,
+            if breaker_window == 0 { "slot".to_string() } else { format!("{}s", breaker_window) }
+        );
+
+        Ok(())
+    }
+
+    // Point the LP token metadata at a new off-chain JSON URI
+    pub fn set_lp_metadata_uri(ctx: Context<SetLpMetadataUri>, uri: String) -> Result<()> {
+        if uri.len() > MAX_URI_LENGTH {
+            return Err(ErrorCode::InvalidMetadataUri.into());
+        }
+
+        let (mint_b, lp_mint, bump) = {
+            let pool = ctx.accounts.pool.load()?;
+            (pool.mint_b, pool.lp_mint, pool.bump)
+        };
+        let seeds: &[&[u8]] = &[
+            b"pool",
+            mint_b.as_ref(),
+            &[bump],
+        ];
+        let signer = &[seeds];
+
+        let metadata = &ctx.accounts.lp_mint_metadata;
+        let data = DataV2 {
+            name: metadata.name.clone(),
+            symbol: metadata.symbol.clone(),
+            uri: uri.clone(),
+            seller_fee_basis_points: metadata.seller_fee_basis_points,
+            creators: None,
+            collection: None,
+            uses: None,
+        };
+
+        update_metadata_accounts_v2(
+            CpiContext::new_with_signer(
+                ctx.accounts.metadata_program.to_account_info(),
+                UpdateMetadataAccountsV2 {
+                    metadata: ctx.accounts.lp_mint_metadata.to_account_info(),
+                    update_authority: ctx.accounts.pool.to_account_info(),
+                },
+                signer,
+            ),
+            None,
+            Some(data),
+            None,
+            None,
+        )?;
+
+        msg!("LP metadata URI for {:?} set to {}", lp_mint, uri);
+
+        Ok(())
+    }
+
+    // Create or update the global config
+    pub fn set_config(ctx: Context<SetConfig>, max_referral_bps: u64, max_creator_fee_bps: u64) -> Result<()> {
+        if max_referral_bps.gt(&10000) {
+            return Err(ErrorCode::ReferralFeeTooHigh.into());
+        }
+        // Referral and creator shares are both taken out of the same fee
+        if max_creator_fee_bps.gt(&(10000 - max_referral_bps)) {
+            return Err(ErrorCode::CreatorFeeTooHigh.into());
+        }
+
+        let config = &mut ctx.accounts.config;
+        config.max_referral_bps = max_referral_bps;
+        config.max_creator_fee_bps = max_creator_fee_bps;
+
+        msg!(
+            "Config updated: max referral {} bps and max creator fee {} bps of the swap fee",
+            max_referral_bps, max_creator_fee_bps
+        );
+
+        Ok(())
+    }
+
+    // Convert a pool from the original Borsh layout to zero-copy, see `migrate.rs`
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+        migrate::migrate_pool(ctx)
+    }
+
+    // Move a version 1 pool's reserves into PDA vaults, see `migrate.rs`
+    pub fn migrate_vaults(ctx: Context<MigrateVaults>) -> Result<()> {
+        migrate::migrate_vaults(ctx)
+    }
+
+    // Permissioned pools, see `permission.rs`
+    pub fn set_pool_access(ctx: Context<SetPoolAccess>, mode: AccessMode, gate_mint: Pubkey) -> Result<()> {
+        permission::set_pool_access(ctx, mode, gate_mint)
+    }
+
+    pub fn add_to_allowlist(ctx: Context<AddToAllowlist>, trader: Pubkey) -> Result<()> {
+        permission::add_to_allowlist(ctx, trader)
+    }
+
+    pub fn remove_from_allowlist(ctx: Context<RemoveFromAllowlist>) -> Result<()> {
+        permission::remove_from_allowlist(ctx)
+    }
+
+    // Creator fees, see `creator.rs`
+    pub fn set_creator_fee(ctx: Context<SetCreatorFee>, creator_fee_bps: u16) -> Result<()> {
+        creator::set_creator_fee(ctx, creator_fee_bps)
+    }
+
+    pub fn claim_creator_fees(ctx: Context<ClaimCreatorFees>) -> Result<()> {
+        creator::claim_creator_fees(ctx)
+    }
+
+    // LP positions with a cost basis, see `lp_position.rs`
+    pub fn create_lp_position(ctx: Context<CreateLpPosition>) -> Result<()> {
+        lp_position::create_lp_position(ctx)
+    }
+
+    pub fn close_lp_position(ctx: Context<CloseLpPosition>) -> Result<()> {
+        lp_position::close_lp_position(ctx)
+    }
+
+    pub fn lp_position_report(ctx: Context<LpPositionView>) -> Result<LpPositionReport> {
+        lp_position::lp_position_report(ctx)
+    }
+
+    // Concentrated liquidity pools, see `concentrated.rs`
+    pub fn initialize_cl_pool(
+        ctx: Context<InitializeClPool>, 
+        tick_spacing: u16, 
+        fee: u64, 
+        sqrt_price: u128
+    ) -> Result<()> {
+        concentrated::initialize_cl_pool(ctx, tick_spacing, fee, sqrt_price)
+    }
+
+    pub fn initialize_tick_array(ctx: Context<InitializeTickArray>, start_tick_index: i32) -> Result<()> {
+        concentrated::initialize_tick_array(ctx, start_tick_index)
+    }
+
+    pub fn open_position(ctx: Context<OpenPosition>, tick_lower: i32, tick_upper: i32) -> Result<()> {
+        concentrated::open_position(ctx, tick_lower, tick_upper)
+    }
+
+    pub fn increase_liquidity(
+        ctx: Context<ModifyLiquidity>, 
+        liquidity: u128, 
+        token_max_a: u64, 
+        token_max_b: u64
+    ) -> Result<()> {
+        concentrated::increase_liquidity(ctx, liquidity, token_max_a, token_max_b)
+    }
+
+    pub fn decrease_liquidity(
+        ctx: Context<ModifyLiquidity>, 
+        liquidity: u128, 
+        token_min_a: u64, 
+        token_min_b: u64
+    ) -> Result<()> {
+        concentrated::decrease_liquidity(ctx, liquidity, token_min_a, token_min_b)
+    }
+
+    pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
+        concentrated::collect_fees(ctx)
+    }
+
+    // LP staking farms, see `farm.rs`
+    pub fn create_farm(ctx: Context<CreateFarm>, reward_per_second: u64, reward_amount: u64) -> Result<()> {
+        farm::create_farm(ctx, reward_per_second, reward_amount)
+    }
+
+    pub fn fund_farm(ctx: Context<FundFarm>, amount: u64) -> Result<()> {
+        farm::fund_farm(ctx, amount)
+    }
+
+    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+        farm::stake(ctx, amount)
+    }
+
+    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+        farm::unstake(ctx, amount)
+    }
+
+    pub fn claim(ctx: Context<Claim>) -> Result<()> {
+        farm::claim(ctx)
+    }
+
+    // Time-locked LP tokens, see `lock.rs`
+    pub fn lock_liquidity(
+        ctx: Context<LockLiquidity>, 
+        lock_id: u64, 
+        amount: u64, 
+        unlock_ts: i64, 
+        beneficiary: Option<Pubkey>
+    ) -> Result<()> {
+        lock::lock_liquidity(ctx, lock_id, amount, unlock_ts, beneficiary)
+    }
+
+    pub fn unlock_liquidity(ctx: Context<UnlockLiquidity>) -> Result<()> {
+        lock::unlock_liquidity(ctx)
+    }
+
+    // Bonding-curve launchpad, see `launch.rs`
+    pub fn create_launch(
+        ctx: Context<CreateLaunch>, 
+        name: String, 
+        symbol: String, 
+        uri: String, 
+        virtual_quote_reserve: u64, 
+        graduation_market_cap: u64
+    ) -> Result<()> {
+        launch::create_launch(ctx, name, symbol, uri, virtual_quote_reserve, graduation_market_cap)
+    }
+
+    pub fn launch_buy(ctx: Context<LaunchTrade>, quote_amount: u64, min_tokens_out: u64) -> Result<()> {
+        launch::launch_buy(ctx, quote_amount, min_tokens_out)
+    }
+
+    pub fn launch_sell(ctx: Context<LaunchTrade>, token_amount: u64, min_quote_out: u64) -> Result<()> {
+        launch::launch_sell(ctx, token_amount, min_quote_out)
+    }
+
+    pub fn graduate(ctx: Context<Graduate>) -> Result<()> {
+        launch::graduate(ctx)
+    }
+
+    // Limit orders, see `orders.rs`
+    pub fn place_limit_order(
+        ctx: Context<PlaceLimitOrder>, 
+        order_id: u64, 
+        amount_in: u64, 
+        min_amount_out: u64, 
+        input_is_a: bool
+    ) -> Result<()> {
+        orders::place_limit_order(ctx, order_id, amount_in, min_amount_out, input_is_a)
+    }
+
+    pub fn cancel_limit_order(ctx: Context<CancelLimitOrder>) -> Result<()> {
+        orders::cancel_limit_order(ctx)
+    }
+
+    // Orders to fill go in `remaining_accounts`, four accounts each
+    pub fn fill_limit_orders<'info>(ctx: Context<'_, '_, 'info, 'info, FillLimitOrders<'info>>) -> Result<()> {
+        orders::fill_limit_orders(ctx)
+    }
+
+    // TWAMM long-term orders, see `twamm.rs`
+    pub fn initialize_twamm(ctx: Context<InitializeTwamm>) -> Result<()> {
+        twamm::initialize_twamm(ctx)
+    }
+
+    pub fn place_long_term_order(
+        ctx: Context<PlaceLongTermOrder>, 
+        order_id: u64, 
+        amount: u64, 
+        sell_a: bool, 
+        intervals: u64
+    ) -> Result<()> {
+        twamm::place_long_term_order(ctx, order_id, amount, sell_a, intervals)
+    }
+
+    pub fn close_long_term_order(ctx: Context<CloseLongTermOrder>) -> Result<()> {
+        twamm::close_long_term_order(ctx)
+    }
+
+    // Recurring swaps, see `dca.rs`
+    pub fn create_dca(
+        ctx: Context<CreateDca>, 
+        dca_id: u64, 
+        total_amount: u64, 
+        amount_per_cycle: u64, 
+        frequency: i64, 
+        max_slippage_bps: u64, 
+        input_is_a: bool
+    ) -> Result<()> {
+        dca::create_dca(ctx, dca_id, total_amount, amount_per_cycle, frequency, max_slippage_bps, input_is_a)
+    }
+
+    pub fn execute_dca(ctx: Context<ExecuteDca>) -> Result<()> {
+        dca::execute_dca(ctx)
+    }
+
+    pub fn cancel_dca(ctx: Context<CancelDca>) -> Result<()> {
+        dca::cancel_dca(ctx)
+    }
+
+    // Tick arrays to cross go in `remaining_accounts`, in swap order
+    pub fn cl_swap<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ClSwap<'info>>, 
+        amount: u64, 
+        min_amount_out: u64, 
+        a_to_b: bool, 
+        sqrt_price_limit: u128
+    ) -> Result<()> {
+        concentrated::cl_swap(ctx, amount, min_amount_out, a_to_b, sqrt_price_limit)
+    }
+}
+
+#[derive(Accounts)]
+pub struct SetLpMetadataUri<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+        has_one = lp_mint @ ErrorCode::InvalidLPMint,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            lp_mint.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub lp_mint_metadata: Box<Account<'info, MetadataAccount>>,
+
+    #[account(address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+    pub metadata_program: Program<'info, Metadata>,
+}
+
+#[derive(Accounts)]
+pub struct SetConfig<'info> {
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + std::mem::size_of::<Config>(),
+        seeds = [b"config"],
+        bump,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(mut, address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCircuitBreaker<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetDynamicFee<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RampAmp<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+        constraint = pool.load()?.curve_type() == CurveType::StableSwap @ ErrorCode::NotStableSwapPool
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveLiquidity<'info> {
+    #[account(
+        mut,
+        has_one = lp_mint @ ErrorCode::InvalidLPMint,
+        seeds = [
+            b"pool", 
+            mint_b.key().as_ref()
+        ],
+        bump = pool.load()?.bump,
+        constraint = pool_token_a.mint == pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA,
+        constraint = pool_token_b.mint == pool.load()?.mint_b @ ErrorCode::InvalidPoolTokenB
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(mut)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(mut)]
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault
+    )]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault
+    )]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub lp_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = user_token_a.owner == user.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = user_token_a.mint == pool.load()?.mint_a @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub user_token_a: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_b.owner == user.key() @ ErrorCode::InvalidUserTokenB,
+        constraint = user_token_b.mint == pool.load()?.mint_b @ ErrorCode::InvalidUserTokenBMint
+    )]
+    pub user_token_b: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_lp_token_account.owner == user.key() @ ErrorCode::InvalidUserLPOwner,
+        constraint = user_lp_token_account.mint == lp_mint.key() @ ErrorCode::InvalidUserLPMint
+    )]
+    pub user_lp_token_account: Account<'info, TokenAccount>,
+
+    // Only required while the pool has open long-term orders
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: Option<AccountLoader<'info, Twamm>>,
+    #[account(mut)]
+    pub twamm_vault_a: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(mut)]
+    pub twamm_vault_b: Option<Box<Account<'info, TokenAccount>>>,
+
+    // Only passed by owners tracking their position, see `lp_position.rs`
+    #[account(
+        mut,
+        seeds = [
+            b"lp_position",
+            pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump = lp_position.bump,
+    )]
+    pub lp_position: Option<Box<Account<'info, LpPosition>>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>
+}
+
+#[derive(Accounts)]
+pub struct ClosePool<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+        close = rent_receiver,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+    // Only exists, and then required, if the creator fee was ever set
+    #[account(
+        mut,
+        seeds = [
+            b"creator_fees",
+            pool.key().as_ref()
+        ],
+        bump = pool.load()?.creator_fee_vault_bump,
+    )]
+    pub creator_fee_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut, seeds = [b"registry"], bump)]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+    // Required for registered pools, left out for pools created before the registry
+    #[account(
+        mut,
+        seeds = [
+            b"pool_entry".as_ref(),
+            &pool.load()?.registry_index.to_le_bytes()
+        ],
+        bump,
+    )]
+    pub registry_entry: Option<Box<Account<'info, PoolRegistryEntry>>>,
+    // The last registry entry and its pool, moved into the freed index. Only
+    // required when the pool isn't the last entry.
+    #[account(
+        mut,
+        seeds = [
+            b"pool_entry".as_ref(),
+            &registry.pool_count.saturating_sub(1).to_le_bytes()
+        ],
+        bump,
+    )]
+    pub last_registry_entry: Option<Box<Account<'info, PoolRegistryEntry>>>,
+    #[account(mut)]
+    pub last_pool: Option<AccountLoader<'info, PoolInfo>>,
+
+    /// CHECK: Receives the rent, checked against the pool's creator
+    #[account(mut, address = pool.load()?.rent_receiver() @ ErrorCode::Unauthorized)]
+    pub rent_receiver: UncheckedAccount<'info>,
+    #[account(
+        constraint = authority.key() == pool.load()?.creator
+            || authority.key() == ADMIN_PUBKEY @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+// Protocol-wide settings, owned by the admin
+#[account]
+pub struct Config {
+    // Upper bound on the share of a swap fee paid to referrers, in bps of the fee
+    pub max_referral_bps: u64,
+    // Upper bound on a pool creator's share of a swap fee, in bps of the fee
+    pub max_creator_fee_bps: u64,
+}
+
+#[event]
+pub struct ReferralFeePaid {
+    pub pool: Pubkey,
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+// Zero-copy so swaps read the pool in place instead of deserializing it. Fields
+// are ordered by alignment so the layout has no implicit padding.
+#[account(zero_copy)]
+pub struct PoolInfo {
+    pub pool: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub lp_mint: Pubkey,
+    pub total_liquidity: u128,
+    // Spot price of token A in token B after the last swap, Q64.64
+    pub last_price: u128,
+    // Sum of `last_price` times seconds held, Q64.64, for TWAPs. Wraps.
+    pub price_cumulative: u128,
+    pub breaker_window_price: u128,
+    pub fees: u64,
+    // StableSwap amplification ramp, unused by constant product pools
+    pub initial_amp: u64,
+    pub target_amp: u64,
+    pub ramp_start_ts: i64,
+    pub ramp_stop_ts: i64,
+    // Token weights in bps, 5000/5000 for non-weighted pools
+    pub weight_a: u64,
+    pub weight_b: u64,
+    // Dynamic fee mode: `fees` is the base fee, raised by recent volatility up to `max_fee`
+    pub max_fee: u64,
+    pub volatility_multiplier: u64,
+    pub volatility_half_life: i64,
+    // Accumulated price movement in bps, halved every `volatility_half_life` seconds
+    pub volatility: u64,
+    pub volatility_updated_ts: i64,
+    // Active TWAMM orders; while non-zero every touch must settle them
+    pub long_term_orders: u64,
+    pub price_cumulative_ts: i64,
+    // Circuit breaker, in bps of spot price, 0 disables a limit
+    pub max_price_impact_bps: u64,
+    pub max_window_movement_bps: u64,
+    // Seconds per movement window, or 0 to limit movement within a slot
+    pub breaker_window: i64,
+    pub breaker_window_ts: i64,
+    pub breaker_window_slot: u64,
+    // Position in the pool registry, valid when `registered` is set
+    pub registry_index: u64,
+    // `CurveType` and `dynamic_fee` as bytes, read through `curve_type()` and `dynamic_fee()`
+    pub curve_type: u8,
+    pub dynamic_fee: u8,
+    // Layout version, see `POOL_VERSION`
+    pub version: u8,
+    // Bumps of the pool PDA, LP mint and vaults, saved at creation
+    pub bump: u8,
+    pub lp_mint_bump: u8,
+    pub vault_a_bump: u8,
+    pub vault_b_bump: u8,
+    // `AccessMode` as a byte, read through `access_mode()`
+    pub access_mode: u8,
+    // Creator's share of swap fees in bps of the fee, see `creator.rs`
+    pub creator_fee_bps: u16,
+    pub creator_fee_vault_bump: u8,
+    // Whether the pool has a registry entry, pools created before the registry don't
+    pub registered: u8,
+    pub _padding: [u8; 4],
+    // Reserve vaults, token accounts owned by the pool at `[b"vault", pool, mint]`
+    pub vault_a: Pubkey,
+    pub vault_b: Pubkey,
+    // Signer of `initialize_pool`, or the launch creator for graduated pools
+    pub creator: Pubkey,
+    // Token whose holders may trade in `AccessMode::TokenGate` pools
+    pub gate_mint: Pubkey,
+    // Cumulative swap stats, including keeper fills and TWAMM settlements. Volume
+    // is the gross amount of each token swapped in or out, fees are in token A
+    // and include referral and creator shares.
+    pub volume_a: u128,
+    pub volume_b: u128,
+    pub fees_collected: u128,
+    pub swap_count: u64,
+    pub last_trade_ts: i64,
+    // Room for fields added by later versions
+    pub _reserved: [u8; POOL_RESERVED_SPACE],
+}
+
+impl PoolInfo {
+    // Account size, including the reserved bytes. New fields are carved out of
+    // `_reserved` so the size never changes.
+    pub const SPACE: usize = 8 + std::mem::size_of::<PoolInfo>();
+
+    pub fn curve_type(&self) -> CurveType {
+        match self.curve_type {
+            1 => CurveType::StableSwap,
+            2 => CurveType::Weighted,
+            _ => CurveType::ConstantProduct,
+        }
+    }
+
+    pub fn dynamic_fee(&self) -> bool {
+        self.dynamic_fee != 0
+    }
+
+    pub fn access_mode(&self) -> AccessMode {
+        match self.access_mode {
+            1 => AccessMode::Allowlist,
+            2 => AccessMode::TokenGate,
+            _ => AccessMode::Open,
+        }
+    }
+
+    // Fresh constant product pool with a static fee
+    pub fn initialize(
+        &mut self, 
+        pool: Pubkey, 
+        mint_a: Pubkey, 
+        mint_b: Pubkey, 
+        lp_mint: Pubkey, 
+        fee: u64, 
+        now: i64
+    ) {
+        self.pool = pool;
+        self.mint_a = mint_a;
+        self.mint_b = mint_b;
+        self.lp_mint = lp_mint;
+        self.fees = fee;
+        self.total_liquidity = 0;
+        self.curve_type = CurveType::ConstantProduct as u8;
+        self.initial_amp = 0;
+        self.target_amp = 0;
+        self.ramp_start_ts = now;
+        self.ramp_stop_ts = now;
+        self.weight_a = WEIGHT_ONE / 2;
+        self.weight_b = WEIGHT_ONE / 2;
+        self.dynamic_fee = 0;
+        self.max_fee = fee;
+        self.long_term_orders = 0;
+        self.price_cumulative = 0;
+        self.price_cumulative_ts = now;
+        self.max_price_impact_bps = 0;
+        self.max_window_movement_bps = 0;
+        self.breaker_window = 0;
+        self.breaker_window_price = 0;
+        self.version = POOL_VERSION;
+    }
+
+    // Save the PDA bumps so later instructions never re-derive them
+    pub fn set_bumps(&mut self, bump: u8, lp_mint_bump: u8) {
+        self.bump = bump;
+        self.lp_mint_bump = lp_mint_bump;
+    }
+
+    // Add a swap to the cumulative stats
+    pub fn record_trade(&mut self, amount_a: u128, amount_b: u128, fee: u128, now: i64) {
+        self.volume_a = self.volume_a.saturating_add(amount_a);
+        self.volume_b = self.volume_b.saturating_add(amount_b);
+        self.fees_collected = self.fees_collected.saturating_add(fee);
+        self.swap_count = self.swap_count.saturating_add(1);
+        self.last_trade_ts = now;
+    }
+
+    // Creator's share of a swap fee, capped by the config's current maximum in
+    // case it was lowered after the creator set their fee
+    pub fn creator_fee(&self, fee_amount: u128, max_creator_fee_bps: u64) -> u128 {
+        fee_amount * (self.creator_fee_bps as u64).min(max_creator_fee_bps) as u128 / 10000
+    }
+
+    // Receives the rent when the pool is closed. Pools from before the creator
+    // was stored refund the admin.
+    pub fn rent_receiver(&self) -> Pubkey {
+        if self.creator == Pubkey::default() {
+            ADMIN_PUBKEY
+        } else {
+            self.creator
+        }
+    }
+
+    pub fn set_vaults(&mut self, vault_a: Pubkey, vault_a_bump: u8, vault_b: Pubkey, vault_b_bump: u8) {
+        self.vault_a = vault_a;
+        self.vault_a_bump = vault_a_bump;
+        self.vault_b = vault_b;
+        self.vault_b_bump = vault_b_bump;
+    }
+
+    pub fn amp(&self, now: i64) -> u64 {
+        current_amp(
+            self.initial_amp,
+            self.target_amp,
+            self.ramp_start_ts,
+            self.ramp_stop_ts,
+            now,
+        )
+    }
+
+    // Volatility accumulator decayed to `now`
+    pub fn decayed_volatility(&self, now: i64) -> u64 {
+        if self.volatility_half_life <= 0 {
+            return 0;
+        }
+        let elapsed = now.saturating_sub(self.volatility_updated_ts).max(0);
+        let half_lives = elapsed / self.volatility_half_life;
+        if half_lives >= 64 {
+            0
+        } else {
+            self.volatility >> half_lives
+        }
+    }
+
+    // Fee in bps charged on a swap at `now`
+    pub fn current_fee(&self, now: i64) -> u64 {
+        if !self.dynamic_fee() {
+            return self.fees;
+        }
+
+        let variable_fee = (self.decayed_volatility(now) as u128)
+            .saturating_mul(self.volatility_multiplier as u128)
+            / 10000;
+        let fee = (self.fees as u128).saturating_add(variable_fee);
+        fee.min(self.max_fee as u128) as u64
+    }
+
+    // Spot price of token A in token B as Q64.64
+    pub fn spot_price(&self, reserve_a: u128, reserve_b: u128, now: i64) -> Option<u128> {
+        match self.curve_type() {
+            CurveType::ConstantProduct => price_x64(reserve_b, reserve_a),
+            CurveType::StableSwap => stable_swap_spot_price(self.amp(now), reserve_a, reserve_b),
+            CurveType::Weighted => price_x64(
+                reserve_b.checked_mul(self.weight_a as u128)?,
+                reserve_a.checked_mul(self.weight_b as u128)?,
+            ),
+        }
+    }
+
+    // Price accumulator extended to `now` at the last recorded price. The TWAP
+    // between two readings is their difference over the elapsed time.
+    pub fn price_cumulative_at(&self, now: i64) -> u128 {
+        let elapsed = now.saturating_sub(self.price_cumulative_ts).max(0) as u128;
+        self.price_cumulative.wrapping_add(self.last_price.wrapping_mul(elapsed))
+    }
+
+    // Record the price after a swap and add its movement to the volatility accumulator
+    pub fn record_price(&mut self, reserve_a: u128, reserve_b: u128, now: i64) {
+        // The outgoing price held until now
+        self.price_cumulative = self.price_cumulative_at(now);
+        self.price_cumulative_ts = now;
+
+        let Some(price) = self.spot_price(reserve_a, reserve_b, now) else {
+            return;
+        };
+
+        let movement_bps = price_change_bps(self.last_price, price);
+
+        self.volatility = self.decayed_volatility(now).saturating_add(movement_bps);
+        self.volatility_updated_ts = now;
+        self.last_price = price;
+    }
+
+    // Reject swaps that move the spot price more than the circuit breaker
+    // allows, either on their own or together with the rest of the window
+    pub fn check_circuit_breaker(
+        &mut self,
+        reserves_before: (u128, u128),
+        reserves_after: (u128, u128),
+        now: i64,
+        slot: u64,
+    ) -> Result<()> {
+        if self.max_price_impact_bps == 0 && self.max_window_movement_bps == 0 {
+            return Ok(());
+        }
+        let (Some(price_before), Some(price_after)) = (
+            self.spot_price(reserves_before.0, reserves_before.1, now),
+            self.spot_price(reserves_after.0, reserves_after.1, now),
+        ) else {
+            return Ok(());
+        };
+
+        let impact_bps = price_change_bps(price_before, price_after);
+        if self.max_price_impact_bps > 0 && impact_bps > self.max_price_impact_bps {
+            msg!("Price impact {} bps exceeds {} bps", impact_bps, self.max_price_impact_bps);
+            return Err(ErrorCode::CircuitBreakerTripped.into());
+        }
+
+        let window_expired = if self.breaker_window == 0 {
+            slot != self.breaker_window_slot
+        } else {
+            now - self.breaker_window_ts >= self.breaker_window
+        };
+        if window_expired || self.breaker_window_price == 0 {
+            self.breaker_window_price = price_before;
+            self.breaker_window_ts = now;
+            self.breaker_window_slot = slot;
+        }
+
+        let movement_bps = price_change_bps(self.breaker_window_price, price_after);
+        if self.max_window_movement_bps > 0 && movement_bps > self.max_window_movement_bps {
+            msg!("Price movement {} bps in window exceeds {} bps", movement_bps, self.max_window_movement_bps);
+            return Err(ErrorCode::CircuitBreakerTripped.into());
+        }
+
+        Ok(())
+    }
+
+    // (input weight, output weight) for a swap in the given direction
+    pub fn weights(&self, input_is_a: bool) -> (u64, u64) {
+        if input_is_a {
+            (self.weight_a, self.weight_b)
+        } else {
+            (self.weight_b, self.weight_a)
+        }
+    }
+
+    // Tokens out for `net_input` tokens in, after fees have been taken
+    pub fn swap_output(
+        &self,
+        input_reserve: u128,
+        output_reserve: u128,
+        net_input: u128,
+        input_is_a: bool,
+        now: i64,
+    ) -> Result<u128> {
+        let output = match self.curve_type() {
+            CurveType::ConstantProduct => {
+                constant_product_output(input_reserve, output_reserve, net_input)
+            }
+            CurveType::StableSwap => {
+                stable_swap_output(self.amp(now), input_reserve, output_reserve, net_input)
+            }
+            CurveType::Weighted => {
+                let max_input = input_reserve * MAX_IN_RATIO_BPS / 10000;
+                if net_input > max_input {
+                    return Err(ErrorCode::WeightedTradeTooLarge.into());
+                }
+
+                let (weight_in, weight_out) = self.weights(input_is_a);
+                let output = weighted_output(
+                    input_reserve, 
+                    output_reserve, 
+                    net_input, 
+                    weight_in, 
+                    weight_out
+                );
+
+                let max_output = output_reserve * MAX_OUT_RATIO_BPS / 10000;
+                if output.is_some_and(|out| out > max_output) {
+                    return Err(ErrorCode::WeightedTradeTooLarge.into());
+                }
+                output
+            }
+        };
+
+        output.ok_or(ErrorCode::InsufficientLiquidity.into())
+    }
+
+    // Value the curve keeps constant: x * y or StableSwap D
+    pub fn invariant(&self, reserve_x: u128, reserve_y: u128, now: i64) -> Result<u128> {
+        let invariant = match self.curve_type() {
+            CurveType::ConstantProduct => reserve_x.checked_mul(reserve_y),
+            CurveType::StableSwap => stable_swap_d(self.amp(now), reserve_x, reserve_y),
+            // Weighted pools only compare invariants as a ratio, see `check_invariant`
+            CurveType::Weighted => None,
+        };
+
+        invariant.ok_or(ErrorCode::IntegerOverflowK.into())
+    }
+
+    // Make sure a swap did not shrink the curve invariant. `before` holds the
+    // (input, output) reserves the swap was priced against, `after` the vault
+    // balances re-read after the transfers minus the fee kept by the pool.
+    pub fn check_invariant(
+        &self,
+        before: (u128, u128),
+        after: (u128, u128),
+        input_is_a: bool,
+        now: i64,
+    ) -> Result<()> {
+        let (invariant_before, invariant_after, holds) = match self.curve_type() {
+            CurveType::ConstantProduct => {
+                let k_before = self
+                    .invariant(before.0, before.1, now)
+                    .map_err(|_| ErrorCode::IntegerOverflow11)?;
+                let k_after = self
+                    .invariant(after.0, after.1, now)
+                    .map_err(|_| ErrorCode::IntegerOverflow12)?;
+                (k_before, k_after, k_after >= k_before)
+            }
+            CurveType::StableSwap => {
+                let d_before = self
+                    .invariant(before.0, before.1, now)
+                    .map_err(|_| ErrorCode::IntegerOverflow11)?;
+                let d_after = self
+                    .invariant(after.0, after.1, now)
+                    .map_err(|_| ErrorCode::IntegerOverflow12)?;
+                (d_before, d_after, d_after >= d_before)
+            }
+            CurveType::Weighted => {
+                let (weight_in, weight_out) = self.weights(input_is_a);
+                let ratio = weighted_invariant_ratio(
+                    before.0, 
+                    before.1, 
+                    after.0, 
+                    after.1, 
+                    weight_in, 
+                    weight_out
+                ).ok_or(ErrorCode::IntegerOverflow12)?;
+                (BONE, ratio, ratio >= BONE)
+            }
+        };
+
+        if !holds {
+            msg!(
+                "Curve invariant decreased: before={}, after={}",
+                invariant_before,
+                invariant_after
+            );
+            return Err(ErrorCode::ConstantProductInvariantViolated.into());
+        }
+
+        Ok(())
+    }
+
+    // Tokens `add_liquidity(amount_a, amount_b)` takes and the LP it mints.
+    // Once the pool holds liquidity only the token B matching `amount_a` at the
+    // pool ratio is taken, and `amount_b` must cover it. Proportional deposits
+    // leave the price unchanged for every curve type, so weighted and
+    // StableSwap pools share the constant product LP math.
+    pub fn deposit_amounts(
+        &self,
+        reserve_a: u128,
+        reserve_b: u128,
+        amount_a: u128,
+        amount_b: u128,
+    ) -> Result<(u64, u64, u128)> {
+        let required_amount_b = if self.total_liquidity > 0 {
+            // Ensure that reserve_a is not zero to avoid division by zero
+            if reserve_a == 0 {
+                return Err(ErrorCode::ZeroReserve.into());
+            }
+            let balanced_amount_b = amount_a
+                .checked_mul(reserve_b)
+                .and_then(|result| result.checked_div(reserve_a))
+                .ok_or(ErrorCode::Overflow)?;
+            if amount_b < balanced_amount_b {
+                // Not enough `Token B` provided to maintain the ratio
+                return Err(ErrorCode::InsufficientTokenB.into());
+            }
+            balanced_amount_b
+        } else {
+            // This is the initial liquidity, so use `amount_a` and `amount_b` directly
+            amount_b
+        };
+
+        let lp_amount = if reserve_a == 0 && reserve_b == 0 {
+            // Initialize the pool by minting LP tokens equal to `amount_a`
+            amount_a
+        } else {
+            let lp_tokens_for_amount_a = amount_a
+                .checked_mul(self.total_liquidity)
+                .and_then(|res| res.checked_div(reserve_a))
+                .ok_or(ErrorCode::Overflow)?;
+            let lp_tokens_for_amount_b = amount_b
+                .checked_mul(self.total_liquidity)
+                .and_then(|res| res.checked_div(reserve_b))
+                .ok_or(ErrorCode::Overflow)?;
+            // Take the minimum of both calculated LP tokens to maintain balance
+            std::cmp::min(lp_tokens_for_amount_a, lp_tokens_for_amount_b)
+        };
+
+        Ok((amount_a as u64, required_amount_b as u64, lp_amount))
+    }
+
+    // Tokens `remove_liquidity(lp_amount)` pays out, pro rata to the reserves
+    pub fn withdraw_amounts(&self, reserve_a: u128, reserve_b: u128, lp_amount: u128) -> Result<(u128, u128)> {
+        require!(self.total_liquidity > 0, ErrorCode::InsufficientLiquidity);
+        let amount_a = lp_amount
+            .checked_mul(reserve_a)
+            .ok_or(ErrorCode::OverflowRemoveLiquidityA)?
+            / self.total_liquidity;
+        let amount_b = lp_amount
+            .checked_mul(reserve_b)
+            .ok_or(ErrorCode::OverflowRemoveLiquidityB)?
+            / self.total_liquidity;
+        Ok((amount_a, amount_b))
+    }
+
+    // Net output of a swap of `amount` and the fee it pays, charged like `buy`
+    // (from the input) when token A goes in and like `sell` (from the output) otherwise
+    pub fn quote_swap(
+        &self,
+        input_reserve: u128,
+        output_reserve: u128,
+        amount: u128,
+        input_is_a: bool,
+        now: i64,
+    ) -> Result<(u128, u128)> {
+        let fee_rate = self.current_fee(now) as u128;
+        if input_is_a {
+            let fee_amount = amount
+                .checked_mul(fee_rate)
+                .and_then(|v| v.checked_div(10000))
+                .ok_or(ErrorCode::IntegerOverflowFeeAmount)?;
+            let output = self.swap_output(input_reserve, output_reserve, amount - fee_amount, true, now)?;
+            Ok((output, fee_amount))
+        } else {
+            let gross_output = self.swap_output(input_reserve, output_reserve, amount, false, now)?;
+            let fee_amount = gross_output
+                .checked_mul(fee_rate)
+                .and_then(|v| v.checked_div(10000))
+                .ok_or(ErrorCode::IntegerOverflowFeeAmount)?;
+            Ok((gross_output - fee_amount, fee_amount))
+        }
+    }
+
+    // Post-trade bookkeeping for swaps priced with `quote_swap`: check the
+    // invariant against the vault balances less the fee kept by the pool, then
+    // feed the new price into the volatility tracker and the swap stats. The
+    // pool keeps the whole fee of these swaps, see `creator.rs`.
+    pub fn settle_swap(
+        &mut self,
+        before: (u128, u128),
+        balances: (u128, u128),
+        fee_amount: u128,
+        input_is_a: bool,
+        now: i64,
+    ) -> Result<()> {
+        let (balance_in, balance_out) = balances;
+        let adjusted = if input_is_a {
+            (balance_in.checked_sub(fee_amount).ok_or(ErrorCode::IntegerOverflow11)?, balance_out)
+        } else {
+            (balance_in, balance_out.checked_sub(fee_amount).ok_or(ErrorCode::IntegerOverflow11)?)
+        };
+        self.check_invariant(before, adjusted, input_is_a, now)?;
+
+        let slot = Clock::get()?.slot;
+        let amount_in = balance_in.saturating_sub(before.0);
+        let amount_out = before.1.saturating_sub(balance_out);
+        if input_is_a {
+            self.check_circuit_breaker(before, balances, now, slot)?;
+            self.record_price(balance_in, balance_out, now);
+            self.record_trade(amount_in, amount_out, fee_amount, now);
+        } else {
+            self.check_circuit_breaker((before.1, before.0), (balance_out, balance_in), now, slot)?;
+            self.record_price(balance_out, balance_in, now);
+            // The fee was taken out of the token A output
+            self.record_trade(amount_out + fee_amount, amount_in, fee_amount, now);
+        }
+
+        Ok(())
+    }
+}
+
+// Relative move from one Q64.64 price to another, in bps
+fn price_change_bps(from: u128, to: u128) -> u64 {
+    if from == 0 {
+        return 0;
+    }
+    u64::try_from(
+        U256::from(from.abs_diff(to)) * U256::from(10000u64) / U256::from(from)
+    ).unwrap_or(u64::MAX)
+}
+
+// Ticker used for a mint in LP token names: its Metaplex symbol, or the start
+// of its address when the mint has no metadata
+fn token_symbol(metadata: &AccountInfo, mint: &Pubkey) -> String {
+    if metadata.owner == &anchor_spl::metadata::ID && !metadata.data_is_empty() {
+        if let Ok(metadata) = MetadataAccount::try_deserialize(&mut &metadata.data.borrow()[..]) {
+            let symbol = metadata.symbol.trim_matches(char::from(0)).trim();
+            if !symbol.is_empty() {
+                return symbol.to_string();
+            }
+        }
+    }
+    mint.to_string().chars().take(4).collect()
+}
+
+// Create the LP mint's metadata, named after the underlying pair, e.g.
+// "BABY-LP SOL/USDC". Returns the name.
+fn create_lp_metadata<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, CreateMetadataAccountsV3<'info>>,
+    symbol_a: &str,
+    symbol_b: &str,
+) -> Result<String> {
+    let name: String = format!("BABY-LP {}/{}", symbol_a, symbol_b)
+        .chars()
+        .take(MAX_NAME_LENGTH)
+        .collect();
+    let symbol: String = format!("{}-{}", symbol_a, symbol_b)
+        .chars()
+        .take(MAX_SYMBOL_LENGTH)
+        .collect();
+
+    // The pool is both mint and update authority of the LP metadata
+    create_metadata_accounts_v3(
+        ctx,
+        DataV2 {
+            name: name.clone(),
+            symbol,
+            uri: String::new(),
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        },
+        true,
+        true,
+        None,
+    )?;
+
+    Ok(name)
+}
+
+// Referral share of `fee_amount` and the referrer token account it is paid to.
+// The referrer must be a token account for `mint`, passed as `remaining_accounts[0]`.
+fn referral_fee<'info>(
+    config: &Option<Box<Account<'info, Config>>>,
+    remaining_accounts: &[AccountInfo<'info>],
+    referral_bps: u64,
+    fee_amount: u128,
+    mint: Pubkey,
+) -> Result<(u128, Option<AccountInfo<'info>>)> {
+    if referral_bps == 0 {
+        return Ok((0, None));
+    }
+
+    let config = config.as_ref().ok_or(ErrorCode::MissingConfig)?;
+    if referral_bps.gt(&config.max_referral_bps) {
+        return Err(ErrorCode::ReferralFeeTooHigh.into());
+    }
+
+    let referrer = remaining_accounts
+        .first()
+        .ok_or(ErrorCode::MissingReferrerAccount)?;
+    if referrer.owner != &spl_token::ID || !referrer.is_writable {
+        return Err(ErrorCode::InvalidReferrerAccount.into());
+    }
+    let referrer_data = AccountState::unpack(&referrer.data.borrow())?;
+    if referrer_data.mint != mint {
+        return Err(ErrorCode::InvalidReferrerAccount.into());
+    }
+
+    let referral_fee = fee_amount
+        .checked_mul(referral_bps as u128)
+        .and_then(|v| v.checked_div(10000))
+        .ok_or(ErrorCode::IntegerOverflowFeeAmount)?;
+
+    Ok((referral_fee, Some(referrer.clone())))
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveType {
+    ConstantProduct,
+    StableSwap,
+    Weighted,
+}
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(
+        init,
+        seeds = [
+            b"pool",
+            mint_b.key().as_ref()
+        ],
+        bump,
+        payer = user,
+        space = PoolInfo::SPACE
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(constraint = mint_a.key() != mint_b.key() @ ErrorCode::SameTokenPool)]
+    pub mint_a: Box<Account<'info, Mint>>, // ADD SOME BOXING HERE
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            mint_a.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_a,
+        token::authority = pool,
+    )]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            mint_b.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_b,
+        token::authority = pool,
+    )]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = user,
+        seeds = [
+            b"lp_mint",
+            pool.key().as_ref()
+        ],
+        bump,
+        mint::decimals = 6,
+        mint::authority = pool,
+    )]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    /// CHECK: Created by the token metadata program
+    #[account(
+        mut,
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            lp_mint.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub lp_mint_metadata: UncheckedAccount<'info>,
+
+    // Metadata of the underlying mints, read for the LP token name. Either may be empty.
+    /// CHECK: Address checked against the metadata PDA, contents parsed in `token_symbol`
+    #[account(
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            mint_a.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub mint_a_metadata: UncheckedAccount<'info>,
+    /// CHECK: Address checked against the metadata PDA, contents parsed in `token_symbol`
+    #[account(
+        seeds = [
+            b"metadata",
+            metadata_program.key().as_ref(),
+            mint_b.key().as_ref()
+        ],
+        seeds::program = metadata_program.key(),
+        bump,
+    )]
+    pub mint_b_metadata: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        seeds = [b"registry"],
+        bump,
+        payer = user,
+        space = 8 + std::mem::size_of::<PoolRegistry>()
+    )]
+    pub registry: Box<Account<'info, PoolRegistry>>,
+    #[account(
+        init,
+        seeds = [
+            b"pool_entry".as_ref(),
+            &registry.pool_count.to_le_bytes()
+        ],
+        bump,
+        payer = user,
+        space = 8 + std::mem::size_of::<PoolRegistryEntry>()
+    )]
+    pub registry_entry: Box<Account<'info, PoolRegistryEntry>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub metadata_program: Program<'info, Metadata>,
+    pub rent: Sysvar<'info, Rent>
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    #[account(
         mut, 
         seeds = [
             b"pool", 
             mint_b.key().as_ref()
         ], 
-        bump,
+        bump = pool.load()?.bump,
+        has_one = lp_mint @ ErrorCode::InvalidLPMint,
     )]
-    pub pool: Box<Account<'info, PoolInfo>>,
+    pub pool: AccountLoader<'info, PoolInfo>,
 
     #[account(mut)]
     pub mint_a: Box<Account<'info, Mint>>,
@@ -800,17 +2439,9 @@ pub struct AddLiquidity<'info> {
     )]
     pub user_token_b: Box<Account<'info, TokenAccount>>,
 
-    #[account(
-        mut,
-        associated_token::mint = mint_a,
-        associated_token::authority = pool,
-    )]
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
     pub pool_token_a: Box<Account<'info, TokenAccount>>,
-    #[account(
-        mut,
-        associated_token::mint = mint_b,
-        associated_token::authority = pool,
-    )]
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
     pub pool_token_b: Box<Account<'info, TokenAccount>>,
 
     #[account(
@@ -828,6 +2459,45 @@ pub struct AddLiquidity<'info> {
     )]
     pub user_lp_token_account: Box<Account<'info, TokenAccount>>,
 
+    // Only required while the pool has open long-term orders
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: Option<AccountLoader<'info, Twamm>>,
+    #[account(mut)]
+    pub twamm_vault_a: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(mut)]
+    pub twamm_vault_b: Option<Box<Account<'info, TokenAccount>>>,
+
+    // Only required in permissioned pools, see `permission.rs`
+    #[account(
+        seeds = [
+            b"allowlist",
+            pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    // Only passed by owners tracking their position, see `lp_position.rs`
+    #[account(
+        mut,
+        seeds = [
+            b"lp_position",
+            pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump = lp_position.bump,
+    )]
+    pub lp_position: Option<Box<Account<'info, LpPosition>>>,
+
     #[account(mut)]
     pub user: Signer<'info>,
 
@@ -854,29 +2524,24 @@ pub struct SellInstruction<'info> {
             mint_a.key().as_ref(), 
             // mint_b.key().as_ref()
         ], 
-        bump,
+        bump = pool.load()?.bump,
         // constraint = pool.pool != Pubkey::default() @ ErrorCode::PoolNotInitialized
+        // Selling pool token B for pool token A
+        constraint = mint_a.key() == pool.load()?.mint_b @ ErrorCode::InvalidPoolTokenB,
+        constraint = mint_b.key() == pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA
     )]
-    pub pool: Box<Account<'info, PoolInfo>>, 
+    pub pool: AccountLoader<'info, PoolInfo>, 
 
     #[account(mut)]
     pub mint_a: Box<Account<'info, Mint>>,
     #[account(mut)]
     pub mint_b: Box<Account<'info, Mint>>,
 
-    // Pool's associated token accounts for Token A and Token B...
-    #[account(
-        mut,
-        associated_token::mint = mint_a,
-        associated_token::authority = pool,
-    )]
+    // Pool's vaults for Token A and Token B...
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
     pub pool_token_a_ata: Box<Account<'info, TokenAccount>>,
 
-    #[account(
-        mut,
-        associated_token::mint = mint_b,
-        associated_token::authority = pool,
-    )]
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
     pub pool_token_b_ata: Box<Account<'info, TokenAccount>>,
 
     // User's associated token accounts for Token A and Token B...
@@ -896,6 +2561,48 @@ pub struct SellInstruction<'info> {
     )]
     pub user_token_b_ata: Box<Account<'info, TokenAccount>>,
 
+    // Only required when paying a referral or creator fee
+    #[account(seeds = [b"config"], bump)]
+    pub config: Option<Box<Account<'info, Config>>>,
+
+    // Only required when the pool pays a creator fee
+    #[account(
+        mut,
+        seeds = [
+            b"creator_fees",
+            pool.key().as_ref()
+        ],
+        bump = pool.load()?.creator_fee_vault_bump,
+    )]
+    pub creator_fee_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    // Only required while the pool has open long-term orders
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: Option<AccountLoader<'info, Twamm>>,
+    #[account(mut)]
+    pub twamm_vault_a: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(mut)]
+    pub twamm_vault_b: Option<Box<Account<'info, TokenAccount>>>,
+
+    // Only required in permissioned pools, see `permission.rs`
+    #[account(
+        seeds = [
+            b"allowlist",
+            pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
     #[account(mut)]
     pub user: Signer<'info>,
     pub rent: Sysvar<'info, Rent>,
@@ -920,29 +2627,22 @@ pub struct BuyInstruction<'info> {
             b"pool", 
             mint_b.key().as_ref()
         ], 
-        bump,
+        bump = pool.load()?.bump,
         // constraint = pool.pool != Pubkey::default() @ ErrorCode::PoolNotInitialized
+        constraint = mint_a.key() == pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA
     )]
-    pub pool: Box<Account<'info, PoolInfo>>, 
+    pub pool: AccountLoader<'info, PoolInfo>, 
 
     #[account(mut)]
     pub mint_a: Box<Account<'info, Mint>>,
     #[account(mut)]
     pub mint_b: Box<Account<'info, Mint>>,
 
-    // Pool's associated token accounts for Token A and Token B...
-    #[account(
-        mut,
-        associated_token::mint = mint_a,
-        associated_token::authority = pool,
-    )]
+    // Pool's vaults for Token A and Token B...
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
     pub pool_token_a_ata: Box<Account<'info, TokenAccount>>,
 
-    #[account(
-        mut,
-        associated_token::mint = mint_b,
-        associated_token::authority = pool,
-    )]
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
     pub pool_token_b_ata: Box<Account<'info, TokenAccount>>,
 
     // User's associated token accounts for Token A and Token B...
@@ -962,6 +2662,48 @@ pub struct BuyInstruction<'info> {
     )]
     pub user_token_b_ata: Box<Account<'info, TokenAccount>>,
 
+    // Only required when paying a referral or creator fee
+    #[account(seeds = [b"config"], bump)]
+    pub config: Option<Box<Account<'info, Config>>>,
+
+    // Only required when the pool pays a creator fee
+    #[account(
+        mut,
+        seeds = [
+            b"creator_fees",
+            pool.key().as_ref()
+        ],
+        bump = pool.load()?.creator_fee_vault_bump,
+    )]
+    pub creator_fee_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    // Only required while the pool has open long-term orders
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: Option<AccountLoader<'info, Twamm>>,
+    #[account(mut)]
+    pub twamm_vault_a: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(mut)]
+    pub twamm_vault_b: Option<Box<Account<'info, TokenAccount>>>,
+
+    // Only required in permissioned pools, see `permission.rs`
+    #[account(
+        seeds = [
+            b"allowlist",
+            pool.key().as_ref(),
+            user.key().as_ref()
+        ],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
     #[account(mut)]
     pub user: Signer<'info>,
     pub rent: Sysvar<'info, Rent>,
@@ -975,6 +2717,9 @@ pub struct BuyInstructionData {
     pub amount: u64,
     pub slippage: u64,
     pub is_buy: bool,
+    // Share of the swap fee paid to the referrer token account in
+    // `remaining_accounts[0]`, in bps of the fee. Zero for no referral.
+    pub referral_bps: u64,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
@@ -982,6 +2727,9 @@ pub struct SellInstructionData {
     pub amount: u64,
     pub slippage: u64,
     pub is_buy: bool,
+    // Share of the swap fee paid to the referrer token account in
+    // `remaining_accounts[0]`, in bps of the fee. Zero for no referral.
+    pub referral_bps: u64,
 }
 
 #[error_code]
@@ -1053,5 +2801,107 @@ pub enum ErrorCode {
     InvalidUserTokenAMint,
     InvalidUserTokenBMint,
     SameTokenPool,
-    InvalidTokenOrder
+    InvalidTokenOrder,
+    #[msg("Amplification coefficient out of range")]
+    InvalidAmp,
+    #[msg("Amplification change exceeds the allowed factor")]
+    AmpChangeTooLarge,
+    #[msg("Amplification ramp is already in progress")]
+    RampInProgress,
+    #[msg("Amplification ramp is too short")]
+    RampTooShort,
+    #[msg("Pool does not use the StableSwap curve")]
+    NotStableSwapPool,
+    #[msg("Token weights out of range")]
+    InvalidWeight,
+    #[msg("Trade exceeds the weighted pool's max in/out ratio")]
+    WeightedTradeTooLarge,
+    #[msg("Invalid tick spacing")]
+    InvalidTickSpacing,
+    #[msg("Invalid tick range")]
+    InvalidTickRange,
+    #[msg("Tick is not on the tick spacing or out of range")]
+    InvalidTickIndex,
+    #[msg("Tick array does not match the pool or tick")]
+    InvalidTickArray,
+    #[msg("Sqrt price out of range")]
+    InvalidSqrtPrice,
+    #[msg("Invalid sqrt price limit")]
+    InvalidSqrtPriceLimit,
+    #[msg("Liquidity overflow or underflow")]
+    LiquidityOverflow,
+    #[msg("Required token amount exceeds the maximum")]
+    TokenMaxExceeded,
+    #[msg("Invalid dynamic fee parameters")]
+    InvalidDynamicFee,
+    #[msg("Referral fee exceeds the configured maximum")]
+    ReferralFeeTooHigh,
+    #[msg("Referral and creator fees require the config account")]
+    MissingConfig,
+    #[msg("Missing referrer token account")]
+    MissingReferrerAccount,
+    #[msg("Referrer token account does not hold the fee token")]
+    InvalidReferrerAccount,
+    #[msg("Metadata URI is too long")]
+    InvalidMetadataUri,
+    #[msg("Invalid farm emission rate")]
+    InvalidEmissionRate,
+    #[msg("Invalid farm reward mint")]
+    InvalidRewardMint,
+    #[msg("Not enough LP tokens staked")]
+    InsufficientStake,
+    #[msg("Unlock time must be in the future")]
+    InvalidUnlockTime,
+    #[msg("Liquidity is still locked")]
+    LiquidityStillLocked,
+    #[msg("Invalid launch parameters")]
+    InvalidLaunchParameters,
+    #[msg("Launch has reached its market cap and must graduate")]
+    LaunchComplete,
+    #[msg("Launch has not reached its market cap or already graduated")]
+    LaunchNotComplete,
+    #[msg("Token is still on its launch bonding curve")]
+    LaunchNotGraduated,
+    #[msg("Order input mint does not match the pool side")]
+    InvalidOrderMint,
+    #[msg("Invalid or mismatched order accounts")]
+    InvalidOrderAccounts,
+    #[msg("Pool has open long-term orders, TWAMM accounts are required")]
+    MissingTwammAccounts,
+    #[msg("Invalid TWAMM accounts")]
+    InvalidTwammAccounts,
+    #[msg("Invalid long-term order duration")]
+    InvalidOrderDuration,
+    #[msg("Expiry interval still has unclosed orders")]
+    TwammIntervalBusy,
+    #[msg("Invalid DCA parameters")]
+    InvalidDcaParameters,
+    #[msg("DCA cycle is not due yet")]
+    DcaNotDue,
+    #[msg("Pool has no price history to average")]
+    TwapUnavailable,
+    #[msg("Invalid circuit breaker settings")]
+    InvalidCircuitBreaker,
+    #[msg("Price impact circuit breaker tripped")]
+    CircuitBreakerTripped,
+    #[msg("Pool already uses the current layout")]
+    PoolAlreadyMigrated,
+    #[msg("Account is not the pool's vault")]
+    InvalidPoolVault,
+    #[msg("Pool still has liquidity, tokens or long-term orders")]
+    PoolNotEmpty,
+    #[msg("Invalid or missing registry entry")]
+    InvalidRegistryEntry,
+    #[msg("Invalid pool access mode")]
+    InvalidAccessMode,
+    #[msg("Trader is not allowed on this pool")]
+    TraderNotAllowed,
+    #[msg("Creator fee exceeds the configured maximum")]
+    CreatorFeeTooHigh,
+    #[msg("Pool pays a creator fee, the creator fee vault is required")]
+    MissingCreatorFeeVault,
+    #[msg("LP position still holds LP tokens")]
+    PositionNotEmpty,
+    #[msg("LP position belongs to another pool")]
+    InvalidPositionAccounts
 }
\ No newline at end of file
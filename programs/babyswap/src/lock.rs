@@ -0,0 +1,225 @@
+// Time-locked LP tokens.
+//
+// LP tokens are escrowed in a vault at `[b"lock_vault", lock]` until `unlock_ts`.
+// Locks point back at their `PoolInfo`, so anyone can check how much of a
+// pool's liquidity is locked and for how long.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{close_account, transfer, CloseAccount, Mint, Token, TokenAccount, Transfer},
+};
+
+use crate::migrate::ReadPool;
+use crate::{ErrorCode, PoolInfo};
+
+#[account]
+pub struct LiquidityLock {
+    pub pool: Pubkey,
+    pub lp_mint: Pubkey,
+    // Pays for the lock and gets the rent back on unlock
+    pub owner: Pubkey,
+    // Receives the LP tokens on unlock, the owner unless set otherwise
+    pub beneficiary: Pubkey,
+    pub lock_id: u64,
+    pub amount: u64,
+    pub locked_ts: i64,
+    pub unlock_ts: i64,
+}
+
+pub fn lock_liquidity(
+    ctx: Context<LockLiquidity>,
+    lock_id: u64,
+    amount: u64,
+    unlock_ts: i64,
+    beneficiary: Option<Pubkey>,
+) -> Result<()> {
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    let now = Clock::get()?.unix_timestamp;
+    if unlock_ts <= now {
+        return Err(ErrorCode::InvalidUnlockTime.into());
+    }
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.user_lp_token.to_account_info(),
+                to: ctx.accounts.lock_vault.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        ),
+        amount,
+    )?;
+
+    let lock = &mut ctx.accounts.lock;
+    lock.pool = ctx.accounts.pool.key();
+    lock.lp_mint = ctx.accounts.lp_mint.key();
+    lock.owner = ctx.accounts.owner.key();
+    lock.beneficiary = beneficiary.unwrap_or(lock.owner);
+    lock.lock_id = lock_id;
+    lock.amount = amount;
+    lock.locked_ts = now;
+    lock.unlock_ts = unlock_ts;
+
+    msg!(
+        "Locked {} LP tokens of pool {:?} until {} for {:?}",
+        amount, lock.pool, unlock_ts, lock.beneficiary
+    );
+
+    Ok(())
+}
+
+pub fn unlock_liquidity(ctx: Context<UnlockLiquidity>) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp;
+    let lock = &ctx.accounts.lock;
+    if now < lock.unlock_ts {
+        return Err(ErrorCode::LiquidityStillLocked.into());
+    }
+
+    let pool = lock.pool;
+    let owner = lock.owner;
+    let lock_id = lock.lock_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[
+        b"lock",
+        pool.as_ref(),
+        owner.as_ref(),
+        &lock_id,
+        &[ctx.bumps.lock],
+    ];
+    let signer = &[seeds];
+
+    let amount = ctx.accounts.lock_vault.amount;
+    transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.lock_vault.to_account_info(),
+                to: ctx.accounts.beneficiary_lp_token.to_account_info(),
+                authority: ctx.accounts.lock.to_account_info(),
+            },
+            signer,
+        ),
+        amount,
+    )?;
+
+    close_account(CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        CloseAccount {
+            account: ctx.accounts.lock_vault.to_account_info(),
+            destination: ctx.accounts.owner.to_account_info(),
+            authority: ctx.accounts.lock.to_account_info(),
+        },
+        signer,
+    ))?;
+
+    msg!("Unlocked {} LP tokens of pool {:?}", amount, pool);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(lock_id: u64)]
+pub struct LockLiquidity<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.read()?.mint_b.as_ref()
+        ],
+        bump = pool.read()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.read()?.lp_mint @ ErrorCode::InvalidLPMint)]
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"lock",
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            &lock_id.to_le_bytes()
+        ],
+        bump,
+        payer = owner,
+        space = 8 + std::mem::size_of::<LiquidityLock>()
+    )]
+    pub lock: Box<Account<'info, LiquidityLock>>,
+
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            b"lock_vault",
+            lock.key().as_ref()
+        ],
+        bump,
+        token::mint = lp_mint,
+        token::authority = lock,
+    )]
+    pub lock_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = user_lp_token.owner == owner.key() @ ErrorCode::InvalidUserLPOwner,
+        constraint = user_lp_token.mint == lp_mint.key() @ ErrorCode::InvalidUserLPMint
+    )]
+    pub user_lp_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct UnlockLiquidity<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"lock",
+            lock.pool.as_ref(),
+            lock.owner.as_ref(),
+            &lock.lock_id.to_le_bytes()
+        ],
+        bump,
+        has_one = owner @ ErrorCode::Unauthorized,
+        has_one = beneficiary @ ErrorCode::Unauthorized,
+        has_one = lp_mint @ ErrorCode::InvalidLPMint,
+        close = owner,
+    )]
+    pub lock: Box<Account<'info, LiquidityLock>>,
+
+    pub lp_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"lock_vault",
+            lock.key().as_ref()
+        ],
+        bump,
+    )]
+    pub lock_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = beneficiary,
+        associated_token::mint = lp_mint,
+        associated_token::authority = beneficiary,
+    )]
+    pub beneficiary_lp_token: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: Lock creator, receives the rent of the lock and its vault
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub beneficiary: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
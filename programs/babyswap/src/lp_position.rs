@@ -0,0 +1,234 @@
+// LP positions with a cost basis.
+//
+// An `LpPosition` at `[b"lp_position", pool, owner]` follows the LP tokens an
+// owner mints and burns through `add_liquidity` and `remove_liquidity` while
+// it is passed in. It keeps the tokens deposited for the LP still held and the
+// LP-weighted spot price at deposit, which `lp_position_report` compares with
+// the pool today.
+//
+// Swaps move reserves along the curve without changing its invariant, so the
+// growth of the invariant behind the position since deposit is what fees
+// earned. The report values everything in token B at the current price:
+// fees are the position value minus that value with the growth taken out, and
+// impermanent loss is how far the fee-less value trails holding the deposit.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+
+use crate::math::{price_x64, stable_swap_d, weighted_invariant_ratio, BONE, U256};
+use crate::migrate::ReadPool;
+use crate::{CurveType, ErrorCode, PoolInfo};
+
+#[account]
+pub struct LpPosition {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    // Tokens deposited for the `lp_amount` LP still held
+    pub deposited_a: u64,
+    pub deposited_b: u64,
+    pub lp_amount: u64,
+    // Spot price of token A in token B at deposit, LP-weighted, Q64.64
+    pub entry_price: u128,
+    pub bump: u8,
+}
+
+impl LpPosition {
+    pub fn record_deposit(&mut self, amount_a: u64, amount_b: u64, lp_amount: u64, price: u128) {
+        let total = self.lp_amount as u128 + lp_amount as u128;
+        if total > 0 {
+            let weighted = U256::from(self.entry_price) * U256::from(self.lp_amount)
+                + U256::from(price) * U256::from(lp_amount);
+            self.entry_price = (weighted / U256::from(total)).as_u128();
+        }
+        self.deposited_a = self.deposited_a.saturating_add(amount_a);
+        self.deposited_b = self.deposited_b.saturating_add(amount_b);
+        self.lp_amount = self.lp_amount.saturating_add(lp_amount);
+    }
+
+    // Release the cost basis of burned LP. LP received from elsewhere has no
+    // basis here, so burns beyond `lp_amount` only empty the position.
+    pub fn record_withdrawal(&mut self, lp_amount: u64) {
+        if self.lp_amount == 0 {
+            return;
+        }
+        let burned = lp_amount.min(self.lp_amount) as u128;
+        let held = self.lp_amount as u128;
+        self.deposited_a -= (self.deposited_a as u128 * burned / held) as u64;
+        self.deposited_b -= (self.deposited_b as u128 * burned / held) as u64;
+        self.lp_amount -= burned as u64;
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone)]
+pub struct LpPositionReport {
+    pub lp_amount: u64,
+    pub deposited_a: u64,
+    pub deposited_b: u64,
+    // Tokens the position withdraws at current reserves
+    pub amount_a: u64,
+    pub amount_b: u64,
+    // Spot prices of token A in token B, Q64.64
+    pub entry_price: u128,
+    pub current_price: u128,
+    // Values in token B at the current price
+    pub position_value: u128,
+    pub hold_value: u128,
+    pub fees_earned: u128,
+    pub impermanent_loss: u128,
+}
+
+// Growth of the pool's invariant from `before` to `after` reserves, Q64.64
+fn invariant_growth(pool: &PoolInfo, before: (u128, u128), after: (u128, u128), now: i64) -> Option<u128> {
+    match pool.curve_type() {
+        CurveType::ConstantProduct => {
+            let before = (U256::from(before.0) * U256::from(before.1)).integer_sqrt();
+            let after = (U256::from(after.0) * U256::from(after.1)).integer_sqrt();
+            price_x64(after.as_u128(), before.as_u128())
+        }
+        CurveType::StableSwap => {
+            let amp = pool.amp(now);
+            price_x64(stable_swap_d(amp, after.0, after.1)?, stable_swap_d(amp, before.0, before.1)?)
+        }
+        CurveType::Weighted => {
+            let ratio = weighted_invariant_ratio(
+                before.0, before.1, after.0, after.1, pool.weight_a, pool.weight_b,
+            )?;
+            price_x64(ratio, BONE)
+        }
+    }
+}
+
+// Value of `amount_a` and `amount_b` in token B at a Q64.64 price
+fn value_in_b(amount_a: u128, amount_b: u128, price: u128) -> u128 {
+    ((U256::from(amount_a) * U256::from(price)) >> 64).as_u128() + amount_b
+}
+
+pub fn create_lp_position(ctx: Context<CreateLpPosition>) -> Result<()> {
+    let position = &mut ctx.accounts.lp_position;
+    position.pool = ctx.accounts.pool.key();
+    position.owner = ctx.accounts.owner.key();
+    position.bump = ctx.bumps.lp_position;
+
+    msg!("LP position created for {:?} in pool {:?}", position.owner, position.pool);
+
+    Ok(())
+}
+
+pub fn close_lp_position(ctx: Context<CloseLpPosition>) -> Result<()> {
+    require!(ctx.accounts.lp_position.lp_amount == 0, ErrorCode::PositionNotEmpty);
+
+    msg!("LP position closed for {:?}", ctx.accounts.owner.key());
+
+    Ok(())
+}
+
+pub fn lp_position_report(ctx: Context<LpPositionView>) -> Result<LpPositionReport> {
+    let pool = ctx.accounts.pool.read()?;
+    let position = &ctx.accounts.lp_position;
+    let now = Clock::get()?.unix_timestamp;
+
+    let reserve_a = ctx.accounts.vault_a.amount as u128;
+    let reserve_b = ctx.accounts.vault_b.amount as u128;
+    let lp_amount = position.lp_amount as u128;
+    let amount_a = (lp_amount * reserve_a).checked_div(pool.total_liquidity).unwrap_or(0);
+    let amount_b = (lp_amount * reserve_b).checked_div(pool.total_liquidity).unwrap_or(0);
+    let current_price = pool.spot_price(reserve_a, reserve_b, now).ok_or(ErrorCode::ZeroReserve)?;
+
+    let deposited = (position.deposited_a as u128, position.deposited_b as u128);
+    let position_value = value_in_b(amount_a, amount_b, current_price);
+    let hold_value = value_in_b(deposited.0, deposited.1, current_price);
+    let fees_earned = match invariant_growth(&pool, deposited, (amount_a, amount_b), now) {
+        Some(growth) if growth > 1 << 64 => {
+            let fee_share = U256::from(growth - (1 << 64)) * U256::from(position_value) / U256::from(growth);
+            fee_share.as_u128()
+        }
+        // Empty position, or the pool shrank relative to the deposit
+        _ => 0,
+    };
+    let impermanent_loss = hold_value.saturating_sub(position_value - fees_earned);
+
+    let report = LpPositionReport {
+        lp_amount: position.lp_amount,
+        deposited_a: position.deposited_a,
+        deposited_b: position.deposited_b,
+        amount_a: amount_a as u64,
+        amount_b: amount_b as u64,
+        entry_price: position.entry_price,
+        current_price,
+        position_value,
+        hold_value,
+        fees_earned,
+        impermanent_loss,
+    };
+    msg!("{:?}", report);
+
+    Ok(report)
+}
+
+#[derive(Accounts)]
+pub struct CreateLpPosition<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.read()?.mint_b.as_ref()
+        ],
+        bump = pool.read()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        init,
+        seeds = [
+            b"lp_position",
+            pool.key().as_ref(),
+            owner.key().as_ref()
+        ],
+        bump,
+        payer = owner,
+        space = 8 + std::mem::size_of::<LpPosition>()
+    )]
+    pub lp_position: Box<Account<'info, LpPosition>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseLpPosition<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"lp_position",
+            lp_position.pool.as_ref(),
+            owner.key().as_ref()
+        ],
+        bump = lp_position.bump,
+        has_one = owner @ ErrorCode::Unauthorized,
+        close = owner,
+    )]
+    pub lp_position: Box<Account<'info, LpPosition>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct LpPositionView<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.read()?.mint_b.as_ref()
+        ],
+        bump = pool.read()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.read()?.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(address = pool.read()?.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(has_one = pool @ ErrorCode::InvalidPositionAccounts)]
+    pub lp_position: Box<Account<'info, LpPosition>>,
+}
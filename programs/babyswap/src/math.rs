@@ -0,0 +1,487 @@
+// Swap curve math shared by `buy` and `sell`.
+//
+// Every function works on raw token amounts and returns `None` on overflow or
+// when the curve cannot be solved, so callers can map it onto an `ErrorCode`.
+
+#[allow(clippy::manual_div_ceil, clippy::assign_op_pattern)]
+mod u256 {
+    uint::construct_uint! {
+        pub struct U256(4);
+    }
+}
+pub use u256::U256;
+
+// StableSwap amplification bounds
+pub const MIN_AMP: u64 = 1;
+pub const MAX_AMP: u64 = 1_000_000;
+// A can be ramped by at most 10x (up or down) in one go
+pub const MAX_AMP_CHANGE: u64 = 10;
+// Ramps must last at least one day
+pub const MIN_RAMP_DURATION: i64 = 86_400;
+
+const N_COINS: u128 = 2;
+const MAX_ITERATIONS: usize = 255;
+
+// Output of a constant product (x * y = k) swap for `net_input` tokens in,
+// rounded down so k never shrinks: out = y * dx / (x + dx)
+pub fn constant_product_output(
+    input_reserve: u128,
+    output_reserve: u128,
+    net_input: u128,
+) -> Option<u128> {
+    let new_input_reserve = input_reserve.checked_add(net_input)?;
+    output_reserve.checked_mul(net_input)?.checked_div(new_input_reserve)
+}
+
+// Amplification coefficient at `now`, linearly interpolated while a ramp is in progress.
+pub fn current_amp(
+    initial_amp: u64,
+    target_amp: u64,
+    ramp_start_ts: i64,
+    ramp_stop_ts: i64,
+    now: i64,
+) -> u64 {
+    if now >= ramp_stop_ts || ramp_stop_ts <= ramp_start_ts {
+        return target_amp;
+    }
+
+    let elapsed = now.saturating_sub(ramp_start_ts).max(0) as u128;
+    let duration = (ramp_stop_ts - ramp_start_ts) as u128;
+
+    if target_amp >= initial_amp {
+        let delta = (target_amp - initial_amp) as u128 * elapsed / duration;
+        initial_amp + delta as u64
+    } else {
+        let delta = (initial_amp - target_amp) as u128 * elapsed / duration;
+        initial_amp - delta as u64
+    }
+}
+
+// StableSwap invariant D for two balances, solved with Newton's method:
+// A * n^n * (x + y) + D = A * n^n * D + D^(n+1) / (n^n * x * y)
+pub fn stable_swap_d(amp: u64, x: u128, y: u128) -> Option<u128> {
+    let sum = x.checked_add(y)?;
+    if sum == 0 {
+        return Some(0);
+    }
+    if x == 0 || y == 0 {
+        return None;
+    }
+
+    let ann = U256::from(amp).checked_mul(U256::from(N_COINS * N_COINS))?;
+    let x = U256::from(x);
+    let y = U256::from(y);
+    let sum = U256::from(sum);
+    let n = U256::from(N_COINS);
+
+    let mut d = sum;
+    for _ in 0..MAX_ITERATIONS {
+        // d_p = D^3 / (n^n * x * y)
+        let mut d_p = d;
+        d_p = d_p.checked_mul(d)?.checked_div(x.checked_mul(n)?)?;
+        d_p = d_p.checked_mul(d)?.checked_div(y.checked_mul(n)?)?;
+
+        let d_prev = d;
+        let numerator = ann
+            .checked_mul(sum)?
+            .checked_add(d_p.checked_mul(n)?)?
+            .checked_mul(d)?;
+        let denominator = ann
+            .checked_sub(U256::one())?
+            .checked_mul(d)?
+            .checked_add(n.checked_add(U256::one())?.checked_mul(d_p)?)?;
+        d = numerator.checked_div(denominator)?;
+
+        let diff = if d > d_prev { d - d_prev } else { d_prev - d };
+        if diff <= U256::one() {
+            // Newton lands within a unit of D either way; settle on the floor
+            // so the post-swap check can compare invariants exactly
+            while d > U256::zero() && !stable_swap_d_within(ann, x, y, d)? {
+                d -= U256::one();
+            }
+            while stable_swap_d_within(ann, x, y, d + U256::one())? {
+                d += U256::one();
+            }
+            return u128::try_from(d).ok();
+        }
+    }
+
+    None
+}
+
+// Whether `d` is at most the StableSwap D of balances `x` and `y`, checked
+// exactly on the invariant multiplied out by 4xy:
+// (Ann - 1) * d * 4xy + d^3 <= Ann * (x + y) * 4xy
+fn stable_swap_d_within(ann: U256, x: U256, y: U256, d: U256) -> Option<bool> {
+    let four_xy = x.checked_mul(y)?.checked_mul(U256::from(N_COINS * N_COINS))?;
+    let lhs = ann
+        .checked_sub(U256::one())?
+        .checked_mul(d)?
+        .checked_mul(four_xy)?
+        .checked_add(d.checked_mul(d)?.checked_mul(d)?)?;
+    let rhs = ann.checked_mul(x.checked_add(y)?)?.checked_mul(four_xy)?;
+    Some(lhs <= rhs)
+}
+
+// Smallest balance of the other coin that keeps D at least `d` when one
+// balance is `x`, so outputs derived from it round in the pool's favour.
+pub fn stable_swap_y(amp: u64, x: u128, d: u128) -> Option<u128> {
+    if x == 0 {
+        return None;
+    }
+
+    let ann = U256::from(amp).checked_mul(U256::from(N_COINS * N_COINS))?;
+    let x = U256::from(x);
+    let d = U256::from(d);
+    let n = U256::from(N_COINS);
+
+    // c = D^3 / (n^n * x * Ann), b = x + D / Ann
+    let c = d
+        .checked_mul(d)?
+        .checked_div(x.checked_mul(n)?)?
+        .checked_mul(d)?
+        .checked_div(ann.checked_mul(n)?)?;
+    let b = x.checked_add(d.checked_div(ann)?)?;
+
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        // y = (y^2 + c) / (2y + b - D)
+        let numerator = y.checked_mul(y)?.checked_add(c)?;
+        let denominator = y
+            .checked_mul(n)?
+            .checked_add(b)?
+            .checked_sub(d)?;
+        y = numerator.checked_div(denominator)?;
+
+        let diff = if y > y_prev { y - y_prev } else { y_prev - y };
+        if diff <= U256::one() {
+            while !stable_swap_d_within(ann, x, y, d)? {
+                y += U256::one();
+            }
+            while y > U256::one() && stable_swap_d_within(ann, x, y - U256::one(), d)? {
+                y -= U256::one();
+            }
+            return u128::try_from(y).ok();
+        }
+    }
+
+    None
+}
+
+// Output of a StableSwap trade for `net_input` tokens in. D is rounded down
+// and the new output balance up, so D never shrinks.
+pub fn stable_swap_output(
+    amp: u64,
+    input_reserve: u128,
+    output_reserve: u128,
+    net_input: u128,
+) -> Option<u128> {
+    let d = stable_swap_d(amp, input_reserve, output_reserve)?;
+    let new_input_reserve = input_reserve.checked_add(net_input)?;
+    let new_output_reserve = stable_swap_y(amp, new_input_reserve, d)?;
+
+    output_reserve.checked_sub(new_output_reserve)
+}
+
+// Weighted pool parameters. Weights are in bps and always sum to `WEIGHT_ONE`.
+pub const WEIGHT_ONE: u64 = 10_000;
+pub const MIN_WEIGHT: u64 = 200;
+// A weighted trade may add at most half the input reserve and take at most
+// a third of the output reserve, which keeps `bpow` inside its fast-converging range
+pub const MAX_IN_RATIO_BPS: u128 = 5_000;
+pub const MAX_OUT_RATIO_BPS: u128 = 3_333;
+
+// Fixed-point one for the weighted math (18 decimals)
+pub const BONE: u128 = 1_000_000_000_000_000_000;
+const BPOW_PRECISION: u128 = BONE / 10_000_000_000;
+// `bpow` is a truncated series, so weighted outputs give up this share of the
+// output reserve (scaled by its inverse weight) to keep its error on the
+// pool's side in both the pricing and the post-swap invariant check
+const WEIGHTED_OUTPUT_MARGIN: u128 = BONE / 1_000_000_000;
+
+fn bmul(a: u128, b: u128) -> Option<u128> {
+    a.checked_mul(b)?.checked_add(BONE / 2)?.checked_div(BONE)
+}
+
+fn bdiv(a: u128, b: u128) -> Option<u128> {
+    a.checked_mul(BONE)?.checked_add(b / 2)?.checked_div(b)
+}
+
+// base^n for a whole exponent, by squaring
+fn bpowi(base: u128, n: u128) -> Option<u128> {
+    let mut a = base;
+    let mut n = n;
+    let mut z = if n & 1 == 1 { a } else { BONE };
+
+    n /= 2;
+    while n != 0 {
+        a = bmul(a, a)?;
+        if n & 1 == 1 {
+            z = bmul(z, a)?;
+        }
+        n /= 2;
+    }
+
+    Some(z)
+}
+
+// base^exp for 0 < exp < 1 using the binomial series, valid for 0 < base < 2
+fn bpow_approx(base: u128, exp: u128) -> Option<u128> {
+    let (x, x_neg) = if base >= BONE { (base - BONE, false) } else { (BONE - base, true) };
+    let mut term = BONE;
+    let mut sum = term;
+    let mut negative = false;
+
+    let mut i: u128 = 1;
+    while term >= BPOW_PRECISION {
+        let big_k = i.checked_mul(BONE)?;
+        let k_minus_one = big_k - BONE;
+        let (c, c_neg) = if exp >= k_minus_one {
+            (exp - k_minus_one, false)
+        } else {
+            (k_minus_one - exp, true)
+        };
+
+        term = bmul(term, bmul(c, x)?)?;
+        term = bdiv(term, big_k)?;
+        if term == 0 {
+            break;
+        }
+
+        if x_neg {
+            negative = !negative;
+        }
+        if c_neg {
+            negative = !negative;
+        }
+        sum = if negative { sum.checked_sub(term)? } else { sum.checked_add(term)? };
+        i += 1;
+    }
+
+    Some(sum)
+}
+
+// base^exp where both are 18-decimal fixed point numbers and 0 < base < 2
+pub fn bpow(base: u128, exp: u128) -> Option<u128> {
+    if base == 0 || base >= 2 * BONE {
+        return None;
+    }
+
+    let whole = exp / BONE;
+    let remain = exp % BONE;
+    let whole_pow = bpowi(base, whole)?;
+    if remain == 0 {
+        return Some(whole_pow);
+    }
+
+    let partial = bpow_approx(base, remain)?;
+    bmul(whole_pow, partial)
+}
+
+// Output of a weighted (constant mean) swap, rounded down and less
+// `WEIGHTED_OUTPUT_MARGIN`: out = Bo * (1 - (Bi / (Bi + Ai)) ^ (Wi / Wo))
+pub fn weighted_output(
+    input_reserve: u128,
+    output_reserve: u128,
+    net_input: u128,
+    weight_in: u64,
+    weight_out: u64,
+) -> Option<u128> {
+    let new_input_reserve = input_reserve.checked_add(net_input)?;
+    let base = bdiv(input_reserve, new_input_reserve)?;
+    let exponent = bdiv(weight_in as u128, weight_out as u128)?;
+    let power = bpow(base, exponent)?;
+    let ratio = BONE.checked_sub(power)?;
+
+    let output = output_reserve.checked_mul(ratio)?.checked_div(BONE)?;
+    let margin = output_reserve
+        .checked_mul(WEIGHTED_OUTPUT_MARGIN * WEIGHT_ONE as u128)?
+        .checked_div(BONE.checked_mul(weight_out as u128)?)?
+        + 1;
+    Some(output.saturating_sub(margin))
+}
+
+// V_after / V_before in 18-decimal fixed point, where V = x^Wx * y^Wy
+pub fn weighted_invariant_ratio(
+    x_before: u128,
+    y_before: u128,
+    x_after: u128,
+    y_after: u128,
+    weight_x: u64,
+    weight_y: u64,
+) -> Option<u128> {
+    let exp_x = bdiv(weight_x as u128, WEIGHT_ONE as u128)?;
+    let exp_y = bdiv(weight_y as u128, WEIGHT_ONE as u128)?;
+    let ratio_x = bpow(bdiv(x_after, x_before)?, exp_x)?;
+    let ratio_y = bpow(bdiv(y_after, y_before)?, exp_y)?;
+
+    bmul(ratio_x, ratio_y)
+}
+
+// numerator / denominator as Q64.64
+pub fn price_x64(numerator: u128, denominator: u128) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    let price = (U256::from(numerator) << 64).checked_div(U256::from(denominator))?;
+    u128::try_from(price).ok()
+}
+
+// Marginal StableSwap price of x in units of y (-dy/dx) as Q64.64:
+// (Ann * 4xy + D^3 / x) / (Ann * 4xy + D^3 / y)
+pub fn stable_swap_spot_price(amp: u64, x: u128, y: u128) -> Option<u128> {
+    let d = U256::from(stable_swap_d(amp, x, y)?);
+    let ann = U256::from(amp).checked_mul(U256::from(N_COINS * N_COINS))?;
+    let d_cubed = d.checked_mul(d)?.checked_mul(d)?;
+    let ann_xy = ann
+        .checked_mul(U256::from(N_COINS * N_COINS))?
+        .checked_mul(U256::from(x))?
+        .checked_mul(U256::from(y))?;
+
+    let numerator = ann_xy.checked_add(d_cubed.checked_div(U256::from(x))?)?;
+    let denominator = ann_xy.checked_add(d_cubed.checked_div(U256::from(y))?)?;
+    let price = (numerator.checked_mul(U256::one() << 64)?).checked_div(denominator)?;
+    u128::try_from(price).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (amp, x, y, dx, D, dy) from Curve's `get_D` and `get_y`, run with
+    // A = 2 * amp since Curve folds one factor of n into A. Curve's `exchange`
+    // keeps one extra unit of dy, so our output may be one higher.
+    const CURVE_VECTORS: [(u64, u128, u128, u128, u128, u128); 6] = [
+        (100, 1_000_000_000_000, 1_000_000_000_000, 1_000_000_000, 2_000_000_000_000, 999_995_024),
+        (100, 2_000_000_000_000, 1_000_000_000_000, 10_000_000_000, 2_999_068_031_122, 9_957_577_293),
+        (
+            100,
+            1_000_000_000_000_000,
+            300_000_000_000_000,
+            1_000_000_000_000,
+            1_298_683_516_736_069,
+            989_473_390_373,
+        ),
+        (1, 1_000_000_000, 5_000_000_000, 100_000_000, 5_390_062_389, 202_744_044),
+        (1_000_000, 1_000_000_000_000, 1_200_000_000_000, 10_000_000_000, 2_199_999_995_416, 10_000_000_876),
+        (2_000, 123_456_789_012, 987_654_321_098, 555_555_555, 1_110_898_611_603, 556_932_241),
+    ];
+
+    #[test]
+    fn stable_swap_matches_curve() {
+        for (amp, x, y, dx, d, dy) in CURVE_VECTORS {
+            assert_eq!(stable_swap_d(amp, x, y), Some(d), "D for amp {amp}");
+            let output = stable_swap_output(amp, x, y, dx).unwrap();
+            assert!((dy..=dy + 1).contains(&output), "amp {amp}: {output} vs Curve {dy}");
+        }
+    }
+
+    #[test]
+    fn stable_swap_d_is_floor() {
+        for (amp, x, y, ..) in CURVE_VECTORS {
+            let ann = U256::from(amp * 4);
+            let d = stable_swap_d(amp, x, y).unwrap();
+            assert!(stable_swap_d_within(ann, x.into(), y.into(), d.into()).unwrap());
+            assert!(!stable_swap_d_within(ann, x.into(), y.into(), (d + 1).into()).unwrap());
+        }
+    }
+
+    #[test]
+    fn stable_swap_balanced_d_is_sum() {
+        for amp in [MIN_AMP, 10, 1_000, MAX_AMP] {
+            assert_eq!(stable_swap_d(amp, 5_000_000, 5_000_000), Some(10_000_000));
+        }
+    }
+
+    #[test]
+    fn stable_swap_never_shrinks_d_at_extreme_amp() {
+        let reserves = [
+            (1_000_000_000_000u128, 1_000_000_000_000u128),
+            (1_000_000_000_000, 10_000_000_000),
+            (1_000_000, 1_000_000_000_000),
+        ];
+        for amp in [MIN_AMP, MAX_AMP] {
+            for (x, y) in reserves {
+                let d_before = stable_swap_d(amp, x, y).unwrap();
+                for dx in [1, 1_000, x / 100, x] {
+                    let output = stable_swap_output(amp, x, y, dx).unwrap();
+                    assert!(output < y);
+                    let d_after = stable_swap_d(amp, x + dx, y - output).unwrap();
+                    assert!(d_after >= d_before, "amp {amp} ({x}, {y}) dx {dx}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stable_swap_max_amp_trades_near_par() {
+        let (x, y) = (1_000_000_000_000u128, 1_000_000_000_000u128);
+        for dx in [1_000_000u128, 1_000_000_000, 100_000_000_000] {
+            let output = stable_swap_output(MAX_AMP, x, y, dx).unwrap();
+            assert!(output <= dx);
+            assert!(output >= dx - dx / 1_000_000, "{output} for {dx}");
+        }
+    }
+
+    // (base, exp, base^exp) from Balancer's `BNum.bpow`
+    const BALANCER_BPOW: [(u128, u128, u128); 6] = [
+        (500_000_000_000_000_000, 500_000_000_000_000_000, 707_106_781_247_605_583),
+        (1_500_000_000_000_000_000, 333_333_333_333_333_333, 1_144_714_242_537_862_373),
+        (900_000_000_000_000_000, 4_000_000_000_000_000_000, 656_100_000_000_000_000),
+        (1_200_000_000_000_000_000, 2_250_000_000_000_000_000, 1_507_154_600_716_281_740),
+        (666_666_666_666_666_666, 4_000_000_000_000_000_000, 197_530_864_197_530_864),
+        (999_999_999_999_999_999, 142_857_142_857_142_857, 1_000_000_000_000_000_000),
+    ];
+
+    // (balance in, weight in, balance out, weight out, amount in, amount out)
+    // from Balancer's `calcOutGivenIn` with no swap fee
+    const BALANCER_OUT_GIVEN_IN: [(u128, u64, u128, u64, u128, u128); 4] = [
+        (1_000_000_000_000, 5_000, 1_000_000_000_000, 5_000, 10_000_000_000, 9_900_990_099),
+        (1_000_000_000_000, 8_000, 4_000_000_000_000, 2_000, 100_000_000_000, 1_267_946_178_540),
+        (500_000_000_000, 2_000, 1_000_000_000_000, 8_000, 200_000_000_000, 80_677_284_758),
+        (1_000_000_000_000_000, 200, 1_000_000_000, 9_800, 100_000_000_000_000, 1_943_215),
+    ];
+
+    #[test]
+    fn bpow_matches_balancer() {
+        for (base, exp, expected) in BALANCER_BPOW {
+            assert_eq!(bpow(base, exp), Some(expected), "{base}^{exp}");
+        }
+        assert_eq!(bpow(0, BONE), None);
+        assert_eq!(bpow(2 * BONE, BONE), None);
+    }
+
+    #[test]
+    fn weighted_output_is_balancer_less_margin() {
+        for (balance_in, weight_in, balance_out, weight_out, amount_in, expected) in BALANCER_OUT_GIVEN_IN {
+            let margin =
+                balance_out * WEIGHTED_OUTPUT_MARGIN * WEIGHT_ONE as u128 / (BONE * weight_out as u128) + 1;
+            let output = weighted_output(balance_in, balance_out, amount_in, weight_in, weight_out).unwrap();
+            // Balancer rounds the last multiplication to nearest, we round down
+            assert!(
+                (expected - margin - 1..=expected - margin).contains(&output),
+                "{output} vs Balancer {expected} less {margin}"
+            );
+        }
+    }
+
+    #[test]
+    fn weighted_output_never_shrinks_invariant() {
+        for (balance_in, weight_in, balance_out, weight_out, _, _) in BALANCER_OUT_GIVEN_IN {
+            for amount_in in [1_000, balance_in / 1_000, balance_in / 10, balance_in / 2] {
+                let output = weighted_output(balance_in, balance_out, amount_in, weight_in, weight_out).unwrap();
+                let ratio = weighted_invariant_ratio(
+                    balance_in,
+                    balance_out,
+                    balance_in + amount_in,
+                    balance_out - output,
+                    weight_in,
+                    weight_out,
+                )
+                .unwrap();
+                assert!(ratio >= BONE, "ratio {ratio} for {amount_in} in");
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+// Pool layout migration.
+//
+// The original program stored pools as Borsh accounts holding only the mints,
+// LP mint, fee and liquidity. The zero-copy `PoolInfo` can't read them, so
+// `migrate_pool` decodes the old layout, reallocs the account to
+// `PoolInfo::SPACE` and writes the fields back in place, as a fresh constant
+// product pool with the same fee. Anyone can migrate a pool by paying the
+// extra rent.
+//
+// During the transition window, instructions that only read a pool accept
+// both layouts through `ReadPool::read`: creating farms, liquidity locks, LP
+// positions, limit orders and DCA orders, and the LP position report.
+// Instructions that write the pool only accept migrated pools, so a client
+// that finds a pool in the old layout sends `migrate_pool` and
+// `migrate_vaults` ahead of them in the same transaction.
+//
+// Those pools hold their reserves in the pool's associated token accounts.
+// `migrate_vaults` moves them into the `[b"vault", pool, mint]` vaults and
+// closes the old accounts.
+
+use anchor_lang::{
+    prelude::*,
+    system_program::{self, Transfer},
+    Discriminator,
+};
+use anchor_spl::{
+    associated_token::{get_associated_token_address, AssociatedToken},
+    token::{self, close_account, CloseAccount, Mint, Token, TokenAccount},
+};
+use std::{cell::Ref, ops::Deref};
+
+use crate::{ErrorCode, PoolInfo, POOL_VERSION};
+
+// Pools converted by `migrate_pool` whose reserves are still in associated
+// token accounts
+const ATA_VAULTS_VERSION: u8 = 1;
+// Size of the original program's pools, whose struct padded out to 160 bytes
+pub const BASELINE_POOL_SPACE: usize = 8 + 160;
+
+// `PoolInfo` as the original program serialized it
+#[derive(AnchorDeserialize)]
+pub struct BaselinePoolInfo {
+    pub pool: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub lp_mint: Pubkey,
+    pub fees: u64,
+    pub total_liquidity: u128,
+}
+
+impl BaselinePoolInfo {
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() != BASELINE_POOL_SPACE || data[..8] != PoolInfo::DISCRIMINATOR {
+            return Err(ErrorCode::PoolNotInitialized.into());
+        }
+        Ok(Self::deserialize(&mut &data[8..])?)
+    }
+
+    // Same as `initialize_pool` with the pool's fee, plus its liquidity.
+    // Bumps and vaults are left to the caller.
+    pub fn write_to(&self, pool: &mut PoolInfo, now: i64) {
+        pool.initialize(self.pool, self.mint_a, self.mint_b, self.lp_mint, self.fees, now);
+        pool.total_liquidity = self.total_liquidity;
+        pool.version = ATA_VAULTS_VERSION;
+    }
+
+    // The pool as it will be once migrated, except that the reserves are
+    // still in its associated token accounts
+    pub fn view(&self, program_id: &Pubkey, now: i64) -> PoolInfo {
+        let mut pool: PoolInfo = bytemuck::Zeroable::zeroed();
+        self.write_to(&mut pool, now);
+        let (pool_key, bump) = Pubkey::find_program_address(&[b"pool", self.mint_b.as_ref()], program_id);
+        pool.set_bumps(bump, lp_mint_bump(&pool_key, &self.lp_mint, program_id));
+        pool.set_vaults(
+            get_associated_token_address(&pool_key, &self.mint_a),
+            0,
+            get_associated_token_address(&pool_key, &self.mint_b),
+            0,
+        );
+        pool
+    }
+}
+
+// A pool borrowed in place, or decoded from the original layout
+pub enum PoolView<'a> {
+    Current(Ref<'a, PoolInfo>),
+    Baseline(Box<PoolInfo>),
+}
+
+impl Deref for PoolView<'_> {
+    type Target = PoolInfo;
+
+    fn deref(&self) -> &PoolInfo {
+        match self {
+            PoolView::Current(pool) => pool,
+            PoolView::Baseline(pool) => pool,
+        }
+    }
+}
+
+// Read-only access to a pool in either layout, for instructions that don't
+// write it
+pub trait ReadPool {
+    fn read(&self) -> Result<PoolView<'_>>;
+}
+
+impl ReadPool for AccountLoader<'_, PoolInfo> {
+    fn read(&self) -> Result<PoolView<'_>> {
+        let info: &AccountInfo = self.as_ref();
+        if info.data_len() != BASELINE_POOL_SPACE {
+            return Ok(PoolView::Current(self.load()?));
+        }
+        let baseline = BaselinePoolInfo::decode(&info.try_borrow_data()?)?;
+        Ok(PoolView::Baseline(Box::new(baseline.view(&crate::ID, Clock::get()?.unix_timestamp))))
+    }
+}
+
+pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+    let info = ctx.accounts.pool.to_account_info();
+    require_keys_eq!(*info.owner, crate::ID, ErrorCode::PoolNotInitialized);
+    if info.data_len() == PoolInfo::SPACE {
+        return Err(ErrorCode::PoolAlreadyMigrated.into());
+    }
+
+    let baseline = BaselinePoolInfo::decode(&info.try_borrow_data()?)?;
+    let (expected_pool, bump) = Pubkey::find_program_address(&[b"pool", baseline.mint_b.as_ref()], ctx.program_id);
+    require_keys_eq!(info.key(), expected_pool, ErrorCode::InvalidTokenOrder);
+    let lp_mint_bump = lp_mint_bump(&expected_pool, &baseline.lp_mint, ctx.program_id);
+
+    // Top up rent for the larger account
+    let rent = Rent::get()?.minimum_balance(PoolInfo::SPACE);
+    let shortfall = rent.saturating_sub(info.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: info.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+    info.realloc(PoolInfo::SPACE, true)?;
+
+    let mut data = info.try_borrow_mut_data()?;
+    data[8..].fill(0);
+    let pool: &mut PoolInfo = bytemuck::from_bytes_mut(&mut data[8..]);
+    baseline.write_to(pool, Clock::get()?.unix_timestamp);
+    pool.set_bumps(bump, lp_mint_bump);
+
+    msg!("Pool {:?} migrated to layout v{}", baseline.pool, ATA_VAULTS_VERSION);
+
+    Ok(())
+}
+
+// Bump of the LP mint PDA, or 0 for pools from before the LP mint was a PDA
+fn lp_mint_bump(pool: &Pubkey, lp_mint: &Pubkey, program_id: &Pubkey) -> u8 {
+    let (lp_mint_pda, bump) = Pubkey::find_program_address(&[b"lp_mint", pool.as_ref()], program_id);
+    if lp_mint_pda == *lp_mint { bump } else { 0 }
+}
+
+pub fn migrate_vaults(ctx: Context<MigrateVaults>) -> Result<()> {
+    let pool_key = ctx.accounts.pool.key();
+    let mint_b = ctx.accounts.mint_b.key();
+    let bump = ctx.bumps.pool;
+    let seeds: &[&[u8]] = &[b"pool", mint_b.as_ref(), &[bump]];
+    let signer = &[seeds];
+
+    let accounts = &ctx.accounts;
+    for (ata, vault) in [
+        (&accounts.pool_token_a, &accounts.vault_a),
+        (&accounts.pool_token_b, &accounts.vault_b),
+    ] {
+        token::transfer(
+            CpiContext::new_with_signer(
+                accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ata.to_account_info(),
+                    to: vault.to_account_info(),
+                    authority: accounts.pool.to_account_info(),
+                },
+                signer,
+            ),
+            ata.amount,
+        )?;
+        close_account(CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ata.to_account_info(),
+                destination: accounts.payer.to_account_info(),
+                authority: accounts.pool.to_account_info(),
+            },
+            signer,
+        ))?;
+    }
+
+    let mut pool = ctx.accounts.pool.load_mut()?;
+    pool.set_vaults(
+        ctx.accounts.vault_a.key(),
+        ctx.bumps.vault_a,
+        ctx.accounts.vault_b.key(),
+        ctx.bumps.vault_b,
+    );
+    pool.version = POOL_VERSION;
+
+    msg!(
+        "Pool {:?} reserves moved to vaults: {} A, {} B",
+        pool_key, ctx.accounts.pool_token_a.amount, ctx.accounts.pool_token_b.amount
+    );
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+    /// CHECK: Pool in the original layout, checked and decoded in `migrate_pool`
+    #[account(mut)]
+    pub pool: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateVaults<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            mint_b.key().as_ref()
+        ],
+        bump,
+        constraint = pool.load()?.version == ATA_VAULTS_VERSION @ ErrorCode::PoolAlreadyMigrated,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool,
+    )]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool,
+    )]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            mint_a.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_a,
+        token::authority = pool,
+    )]
+    pub vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"vault",
+            pool.key().as_ref(),
+            mint_b.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_b,
+        token::authority = pool,
+    )]
+    pub vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CurveType;
+
+    #[test]
+    fn migrates_baseline_layout() {
+        let (pool_key, mint_a, mint_b, lp_mint) =
+            (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut data = PoolInfo::DISCRIMINATOR.to_vec();
+        for key in [pool_key, mint_a, mint_b, lp_mint] {
+            data.extend_from_slice(key.as_ref());
+        }
+        data.extend_from_slice(&30u64.to_le_bytes());
+        data.extend_from_slice(&1_000_000u128.to_le_bytes());
+        data.resize(BASELINE_POOL_SPACE, 0);
+
+        let baseline = BaselinePoolInfo::decode(&data).unwrap();
+        let mut pool: PoolInfo = bytemuck::Zeroable::zeroed();
+        baseline.write_to(&mut pool, 1_700_000_000);
+
+        assert_eq!(pool.pool, pool_key);
+        assert_eq!((pool.mint_a, pool.mint_b, pool.lp_mint), (mint_a, mint_b, lp_mint));
+        assert_eq!(pool.fees, 30);
+        assert_eq!(pool.max_fee, 30);
+        assert_eq!(pool.total_liquidity, 1_000_000);
+        assert_eq!(pool.curve_type(), CurveType::ConstantProduct);
+        assert_eq!(pool.dynamic_fee, 0);
+        assert_eq!((pool.initial_amp, pool.target_amp), (0, 0));
+        assert_eq!((pool.weight_a, pool.weight_b), (5000, 5000));
+        assert_eq!((pool.max_price_impact_bps, pool.max_window_movement_bps), (0, 0));
+        assert_eq!(pool.price_cumulative_ts, 1_700_000_000);
+        assert_eq!(pool.creator, Pubkey::default());
+        assert_eq!(pool.vault_a, Pubkey::default());
+        assert_eq!(pool.version, ATA_VAULTS_VERSION);
+
+        // Same as a fresh pool apart from the liquidity and version
+        let mut fresh: PoolInfo = bytemuck::Zeroable::zeroed();
+        fresh.initialize(pool_key, mint_a, mint_b, lp_mint, 30, 1_700_000_000);
+        fresh.total_liquidity = 1_000_000;
+        fresh.version = ATA_VAULTS_VERSION;
+        assert_eq!(bytemuck::bytes_of(&pool), bytemuck::bytes_of(&fresh));
+    }
+
+    #[test]
+    fn views_baseline_pools_with_their_reserves_in_place() {
+        let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (pool_key, bump) = Pubkey::find_program_address(&[b"pool", mint_b.as_ref()], &crate::ID);
+        let (lp_mint, lp_mint_bump) = Pubkey::find_program_address(&[b"lp_mint", pool_key.as_ref()], &crate::ID);
+        let baseline = BaselinePoolInfo { pool: pool_key, mint_a, mint_b, lp_mint, fees: 30, total_liquidity: 1_000 };
+
+        let pool = baseline.view(&crate::ID, 0);
+        assert_eq!((pool.bump, pool.lp_mint_bump), (bump, lp_mint_bump));
+        assert_eq!(pool.vault_a, get_associated_token_address(&pool_key, &mint_a));
+        assert_eq!(pool.vault_b, get_associated_token_address(&pool_key, &mint_b));
+        assert_eq!(pool.total_liquidity, 1_000);
+    }
+
+    #[test]
+    fn rejects_foreign_accounts() {
+        let data = vec![0; BASELINE_POOL_SPACE];
+        assert!(BaselinePoolInfo::decode(&data).is_err());
+    }
+}
@@ -0,0 +1,432 @@
+// On-chain limit orders.
+//
+// An order escrows its input tokens and names the least it will accept for
+// them. Keepers crank `fill_limit_orders` with any orders that the pool can now
+// fill at or above that price; each fill swaps the whole order through the
+// pool and pays the keeper a small tip out of the output.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{close_account, transfer, CloseAccount, Mint, Token, TokenAccount, Transfer},
+};
+
+use crate::permission::{self, AllowlistEntry};
+use crate::twamm::{self, Twamm};
+use crate::migrate::ReadPool;
+use crate::{ErrorCode, PoolInfo};
+
+// Keeper tip, in bps of the order's output
+pub const KEEPER_TIP_BPS: u128 = 10;
+// Accounts per order in `fill_limit_orders`' remaining accounts
+const ORDER_ACCOUNTS: usize = 4;
+
+#[account]
+pub struct LimitOrder {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub order_id: u64,
+    // Token A in for token B out, as in `buy`, or the reverse as in `sell`
+    pub input_is_a: bool,
+    pub amount_in: u64,
+    // Least the owner receives after fees and the keeper tip
+    pub min_amount_out: u64,
+    pub bump: u8,
+}
+
+impl LimitOrder {
+    pub fn mints(&self, pool: &PoolInfo) -> (Pubkey, Pubkey) {
+        if self.input_is_a {
+            (pool.mint_a, pool.mint_b)
+        } else {
+            (pool.mint_b, pool.mint_a)
+        }
+    }
+}
+
+pub fn place_limit_order(
+    ctx: Context<PlaceLimitOrder>,
+    order_id: u64,
+    amount_in: u64,
+    min_amount_out: u64,
+    input_is_a: bool,
+) -> Result<()> {
+    permission::check_access(
+        &*ctx.accounts.pool.read()?,
+        &ctx.accounts.owner.key(),
+        &ctx.accounts.allowlist_entry,
+        &ctx.accounts.gate_token,
+    )?;
+    require!(amount_in > 0 && min_amount_out > 0, ErrorCode::InvalidAmount);
+
+    let order = &mut ctx.accounts.order;
+    order.pool = ctx.accounts.pool.key();
+    order.owner = ctx.accounts.owner.key();
+    order.order_id = order_id;
+    order.input_is_a = input_is_a;
+    order.amount_in = amount_in;
+    order.min_amount_out = min_amount_out;
+    order.bump = ctx.bumps.order;
+
+    let (input_mint, _) = order.mints(&*ctx.accounts.pool.read()?);
+    require_keys_eq!(ctx.accounts.input_mint.key(), input_mint, ErrorCode::InvalidOrderMint);
+
+    transfer(
+        CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.owner_input_token.to_account_info(),
+                to: ctx.accounts.order_vault.to_account_info(),
+                authority: ctx.accounts.owner.to_account_info(),
+            },
+        ),
+        amount_in,
+    )?;
+
+    msg!(
+        "Limit Order Placed: {} tokens of {:?} for at least {}",
+        amount_in, input_mint, min_amount_out
+    );
+
+    Ok(())
+}
+
+pub fn cancel_limit_order(ctx: Context<CancelLimitOrder>) -> Result<()> {
+    let order = &ctx.accounts.order;
+    let pool = order.pool;
+    let owner = order.owner;
+    let order_id = order.order_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"order", pool.as_ref(), owner.as_ref(), &order_id, &[order.bump]];
+    let signer = &[seeds];
+
+    let amount = ctx.accounts.order_vault.amount;
+    transfer(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Transfer {
+                from: ctx.accounts.order_vault.to_account_info(),
+                to: ctx.accounts.owner_input_token.to_account_info(),
+                authority: ctx.accounts.order.to_account_info(),
+            },
+            signer,
+        ),
+        amount,
+    )?;
+
+    close_account(CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        CloseAccount {
+            account: ctx.accounts.order_vault.to_account_info(),
+            destination: ctx.accounts.owner.to_account_info(),
+            authority: ctx.accounts.order.to_account_info(),
+        },
+        signer,
+    ))?;
+
+    msg!("Limit Order Cancelled: returned {} tokens", amount);
+
+    Ok(())
+}
+
+// Orders go in `remaining_accounts` as [order, order vault, owner's output
+// token account, owner] each. Orders the pool can't fill yet are skipped.
+pub fn fill_limit_orders<'info>(ctx: Context<'_, '_, 'info, 'info, FillLimitOrders<'info>>) -> Result<()> {
+    let orders = ctx.remaining_accounts.chunks_exact(ORDER_ACCOUNTS);
+    require!(
+        !ctx.remaining_accounts.is_empty() && orders.remainder().is_empty(),
+        ErrorCode::InvalidOrderAccounts
+    );
+
+    // Quote against the reserves left after long-term orders have traded
+    let accounts = &mut *ctx.accounts;
+    twamm::settle_if_needed(
+        &accounts.pool,
+        (&mut accounts.pool_token_a, &mut accounts.pool_token_b),
+        &accounts.twamm,
+        (&mut accounts.twamm_vault_a, &mut accounts.twamm_vault_b),
+        &accounts.token_program,
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let pool_key = ctx.accounts.pool.key();
+    let (mint_b, pool_bump) = {
+        let pool = ctx.accounts.pool.load()?;
+        (pool.mint_b, pool.bump)
+    };
+    let pool_seeds: &[&[u8]] = &[b"pool", mint_b.as_ref(), &[pool_bump]];
+    let pool_signer = &[pool_seeds];
+
+    let mut filled = 0;
+    for accounts in orders {
+        let order = Account::<LimitOrder>::try_from(&accounts[0])?;
+        let order_vault = Account::<TokenAccount>::try_from(&accounts[1])?;
+        let owner_output_token = Account::<TokenAccount>::try_from(&accounts[2])?;
+        let owner = &accounts[3];
+
+        let (input_mint, output_mint) = order.mints(&*ctx.accounts.pool.load()?);
+        require_keys_eq!(order.pool, pool_key, ErrorCode::InvalidOrderAccounts);
+        require_keys_eq!(
+            order_vault.key(),
+            Pubkey::find_program_address(&[b"order_vault", order.key().as_ref()], ctx.program_id).0,
+            ErrorCode::InvalidOrderAccounts
+        );
+        require_keys_eq!(order_vault.mint, input_mint, ErrorCode::InvalidOrderAccounts);
+        require_keys_eq!(owner_output_token.owner, order.owner, ErrorCode::InvalidOrderAccounts);
+        require_keys_eq!(owner_output_token.mint, output_mint, ErrorCode::InvalidOrderAccounts);
+        require_keys_eq!(owner.key(), order.owner, ErrorCode::InvalidOrderAccounts);
+
+        let input_is_a = order.input_is_a;
+        let (pool_input, pool_output, keeper_output) = if input_is_a {
+            (&ctx.accounts.pool_token_a, &ctx.accounts.pool_token_b, &ctx.accounts.keeper_token_b)
+        } else {
+            (&ctx.accounts.pool_token_b, &ctx.accounts.pool_token_a, &ctx.accounts.keeper_token_a)
+        };
+        let input_reserve = pool_input.amount as u128;
+        let output_reserve = pool_output.amount as u128;
+
+        let (output, fee_amount) = ctx.accounts.pool.load()?.quote_swap(
+            input_reserve,
+            output_reserve,
+            order.amount_in as u128,
+            input_is_a,
+            now,
+        )?;
+        let tip = output * KEEPER_TIP_BPS / 10000;
+        if output - tip < order.min_amount_out as u128 {
+            msg!("Limit Order {:?} not fillable yet: {} < {}", order.key(), output - tip, order.min_amount_out);
+            continue;
+        }
+
+        let order_id = order.order_id.to_le_bytes();
+        let order_seeds: &[&[u8]] = &[b"order", pool_key.as_ref(), order.owner.as_ref(), &order_id, &[order.bump]];
+        let order_signer = &[order_seeds];
+
+        transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: order_vault.to_account_info(),
+                    to: pool_input.to_account_info(),
+                    authority: order.to_account_info(),
+                },
+                order_signer,
+            ),
+            order.amount_in,
+        )?;
+
+        for (to, amount) in [
+            (owner_output_token.to_account_info(), output - tip),
+            (keeper_output.to_account_info(), tip),
+        ] {
+            if amount > 0 {
+                transfer(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        Transfer {
+                            from: pool_output.to_account_info(),
+                            to,
+                            authority: ctx.accounts.pool.to_account_info(),
+                        },
+                        pool_signer,
+                    ),
+                    amount as u64,
+                )?;
+            }
+        }
+
+        close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: order_vault.to_account_info(),
+                destination: owner.clone(),
+                authority: order.to_account_info(),
+            },
+            order_signer,
+        ))?;
+        order.close(owner.clone())?;
+
+        ctx.accounts.pool_token_a.reload()?;
+        ctx.accounts.pool_token_b.reload()?;
+        let balances = if input_is_a {
+            (ctx.accounts.pool_token_a.amount as u128, ctx.accounts.pool_token_b.amount as u128)
+        } else {
+            (ctx.accounts.pool_token_b.amount as u128, ctx.accounts.pool_token_a.amount as u128)
+        };
+        ctx.accounts.pool.load_mut()?.settle_swap(
+            (input_reserve, output_reserve),
+            balances,
+            fee_amount,
+            input_is_a,
+            now,
+        )?;
+
+        filled += 1;
+        msg!(
+            "Limit Order {:?} filled: {} in, {} out, keeper tip {}",
+            order.key(), order.amount_in, output - tip, tip
+        );
+    }
+
+    msg!("Filled {} of {} limit orders", filled, ctx.remaining_accounts.len() / ORDER_ACCOUNTS);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: u64)]
+pub struct PlaceLimitOrder<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.read()?.mint_b.as_ref()
+        ],
+        bump = pool.read()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        init,
+        seeds = [
+            b"order",
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            &order_id.to_le_bytes()
+        ],
+        bump,
+        payer = owner,
+        space = 8 + std::mem::size_of::<LimitOrder>()
+    )]
+    pub order: Box<Account<'info, LimitOrder>>,
+
+    pub input_mint: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = owner,
+        seeds = [
+            b"order_vault",
+            order.key().as_ref()
+        ],
+        bump,
+        token::mint = input_mint,
+        token::authority = order,
+    )]
+    pub order_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = owner_input_token.owner == owner.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = owner_input_token.mint == input_mint.key() @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub owner_input_token: Box<Account<'info, TokenAccount>>,
+
+    // Only required in permissioned pools, see `permission.rs`
+    #[account(
+        seeds = [
+            b"allowlist",
+            pool.key().as_ref(),
+            owner.key().as_ref()
+        ],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CancelLimitOrder<'info> {
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::Unauthorized,
+        close = owner,
+    )]
+    pub order: Box<Account<'info, LimitOrder>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"order_vault",
+            order.key().as_ref()
+        ],
+        bump,
+    )]
+    pub order_vault: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = owner_input_token.owner == owner.key() @ ErrorCode::InvalidUserTokenA
+    )]
+    pub owner_input_token: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FillLimitOrders<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(address = pool.load()?.mint_b @ ErrorCode::InvalidPoolTokenB)]
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    // Keeper tips are paid in whichever token the order receives
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        associated_token::mint = mint_a,
+        associated_token::authority = keeper,
+    )]
+    pub keeper_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        associated_token::mint = mint_b,
+        associated_token::authority = keeper,
+    )]
+    pub keeper_token_b: Box<Account<'info, TokenAccount>>,
+
+    // Only required while the pool has open long-term orders
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: Option<AccountLoader<'info, Twamm>>,
+    #[account(mut)]
+    pub twamm_vault_a: Option<Box<Account<'info, TokenAccount>>>,
+    #[account(mut)]
+    pub twamm_vault_b: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
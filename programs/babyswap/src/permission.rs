@@ -0,0 +1,142 @@
+// Permissioned pools.
+//
+// A pool in `Allowlist` mode only trades with signers that have an
+// `AllowlistEntry` at `[b"allowlist", pool, trader]`, written by the admin. In
+// `TokenGate` mode the signer instead needs a token account holding the pool's
+// `gate_mint`, e.g. a KYC credential or NFT. Swaps, deposits and orders that
+// later trade against the pool check access; withdrawals and cancels don't, so
+// a trader removed from a pool can always get their tokens out.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+
+use crate::{ErrorCode, PoolInfo, ADMIN_PUBKEY};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessMode {
+    Open,
+    Allowlist,
+    TokenGate,
+}
+
+#[account]
+pub struct AllowlistEntry {
+    pub pool: Pubkey,
+    pub trader: Pubkey,
+    pub bump: u8,
+}
+
+// Fail unless `trader` may trade on `pool`. The allowlist entry is only
+// passed if its seeds matched the pool and trader.
+pub fn check_access(
+    pool: &PoolInfo,
+    trader: &Pubkey,
+    allowlist_entry: &Option<Box<Account<AllowlistEntry>>>,
+    gate_token: &Option<Box<Account<TokenAccount>>>,
+) -> Result<()> {
+    let allowed = match pool.access_mode() {
+        AccessMode::Open => true,
+        AccessMode::Allowlist => allowlist_entry.is_some(),
+        AccessMode::TokenGate => gate_token.as_ref().is_some_and(|token| {
+            token.owner == *trader && token.mint == pool.gate_mint && token.amount > 0
+        }),
+    };
+    require!(allowed, ErrorCode::TraderNotAllowed);
+    Ok(())
+}
+
+pub fn set_pool_access(ctx: Context<SetPoolAccess>, mode: AccessMode, gate_mint: Pubkey) -> Result<()> {
+    if mode == AccessMode::TokenGate && gate_mint == Pubkey::default() {
+        return Err(ErrorCode::InvalidAccessMode.into());
+    }
+
+    let mut pool = ctx.accounts.pool.load_mut()?;
+    pool.access_mode = mode as u8;
+    pool.gate_mint = if mode == AccessMode::TokenGate { gate_mint } else { Pubkey::default() };
+
+    msg!("Pool {:?} access set to {:?}, gate mint {:?}", pool.pool, mode, pool.gate_mint);
+
+    Ok(())
+}
+
+pub fn add_to_allowlist(ctx: Context<AddToAllowlist>, trader: Pubkey) -> Result<()> {
+    let entry = &mut ctx.accounts.allowlist_entry;
+    entry.pool = ctx.accounts.pool.key();
+    entry.trader = trader;
+    entry.bump = ctx.bumps.allowlist_entry;
+
+    msg!("Trader {:?} allowed on pool {:?}", trader, entry.pool);
+
+    Ok(())
+}
+
+pub fn remove_from_allowlist(ctx: Context<RemoveFromAllowlist>) -> Result<()> {
+    let entry = &ctx.accounts.allowlist_entry;
+    msg!("Trader {:?} removed from pool {:?}", entry.trader, entry.pool);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetPoolAccess<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(trader: Pubkey)]
+pub struct AddToAllowlist<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        init,
+        seeds = [
+            b"allowlist",
+            pool.key().as_ref(),
+            trader.as_ref()
+        ],
+        bump,
+        payer = admin,
+        space = 8 + std::mem::size_of::<AllowlistEntry>()
+    )]
+    pub allowlist_entry: Box<Account<'info, AllowlistEntry>>,
+
+    #[account(mut, address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFromAllowlist<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"allowlist",
+            allowlist_entry.pool.as_ref(),
+            allowlist_entry.trader.as_ref()
+        ],
+        bump = allowlist_entry.bump,
+        close = admin,
+    )]
+    pub allowlist_entry: Box<Account<'info, AllowlistEntry>>,
+
+    #[account(mut, address = ADMIN_PUBKEY @ ErrorCode::Unauthorized)]
+    pub admin: Signer<'info>,
+}
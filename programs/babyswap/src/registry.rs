@@ -0,0 +1,57 @@
+// On-chain pool registry.
+//
+// Every pool created by `initialize_pool` or a launch graduation is appended to
+// a dense index: `PoolRegistry` counts the entries and entry `i` lives at
+// `[b"pool_entry", i]`. Clients page through all pools by deriving entry
+// addresses for a range of indexes, without any off-chain database. Closing a
+// pool moves the last entry into its index, so the range stays dense.
+
+use anchor_lang::prelude::*;
+
+use crate::{ErrorCode, PoolInfo};
+
+#[account]
+pub struct PoolRegistry {
+    pub pool_count: u64,
+}
+
+#[account]
+pub struct PoolRegistryEntry {
+    pub index: u64,
+    pub pool: Pubkey,
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+}
+
+impl PoolRegistry {
+    // Record `pool` in the freshly created entry at index `pool_count`
+    pub fn append(&mut self, entry: &mut PoolRegistryEntry, pool: &mut PoolInfo) {
+        entry.index = self.pool_count;
+        entry.pool = pool.pool;
+        entry.mint_a = pool.mint_a;
+        entry.mint_b = pool.mint_b;
+        pool.registry_index = self.pool_count;
+        pool.registered = 1;
+        self.pool_count += 1;
+    }
+
+    // Drop `entry` by moving the last entry, `last`, into its index and
+    // repointing `last_pool`. The caller closes the `last` account.
+    pub fn swap_remove(
+        &mut self,
+        entry: &mut PoolRegistryEntry,
+        last: &PoolRegistryEntry,
+        last_pool: &mut PoolInfo,
+    ) -> Result<()> {
+        require!(
+            last.index + 1 == self.pool_count && last.pool == last_pool.pool,
+            ErrorCode::InvalidRegistryEntry
+        );
+        entry.pool = last.pool;
+        entry.mint_a = last.mint_a;
+        entry.mint_b = last.mint_b;
+        last_pool.registry_index = entry.index;
+        self.pool_count -= 1;
+        Ok(())
+    }
+}
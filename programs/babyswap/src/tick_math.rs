@@ -0,0 +1,364 @@
+// Tick and liquidity math for concentrated liquidity pools.
+//
+// Prices are stored as sqrt(price) in Q64.64 fixed point, where price is the
+// amount of token B per token A and tick `i` sits at price 1.0001^i.
+
+use crate::math::U256;
+
+pub const MIN_TICK: i32 = -443636;
+pub const MAX_TICK: i32 = 443636;
+pub const MIN_SQRT_PRICE: u128 = 4295048017;
+pub const MAX_SQRT_PRICE: u128 = 79226673515401279992447579062;
+
+// 2^128 / sqrt(1.0001)^(2^i), one entry per bit of |tick|
+const TICK_RATIOS: [u128; 19] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e2139,
+    0xfff2e50f5f656932ef12357cf3c7fdcb,
+    0xffe5caca7e10e4e61c3624eaa0941ccf,
+    0xffcb9843d60f6159c9db58835c926643,
+    0xff973b41fa98c081472e6896dfb254bf,
+    0xff2ea16466c96a3843ec78b326b52860,
+    0xfe5dee046a99a2a811c461f1969c3052,
+    0xfcbe86c7900a88aedcffc83b479aa3a3,
+    0xf987a7253ac413176f2b074cf7815e53,
+    0xf3392b0822b70005940c7a398e4b70f2,
+    0xe7159475a2c29b7443b29c7fa6e889d8,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e4,
+    0x70d869a156d2a1b890bb3df62baf32f6,
+    0x31be135f97d08fd981231505542fcfa5,
+    0x9aa508b5b7a84e1c677de54f3e99bc8,
+    0x5d6af8dedb81196699c329225ee604,
+    0x2216e584f5fa1ea926041bedfe97,
+];
+
+fn div_round_up(numerator: U256, denominator: U256) -> Option<U256> {
+    let quotient = numerator.checked_div(denominator)?;
+    if numerator % denominator == U256::zero() {
+        Some(quotient)
+    } else {
+        quotient.checked_add(U256::one())
+    }
+}
+
+// sqrt(1.0001^tick) as Q64.64
+pub fn sqrt_price_at_tick(tick: i32) -> Option<u128> {
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK as u32 {
+        return None;
+    }
+
+    // Q128.128 product of the ratios for every set bit
+    let mut ratio = U256::one() << 128;
+    for (bit, tick_ratio) in TICK_RATIOS.iter().enumerate() {
+        if abs_tick & (1 << bit) != 0 {
+            ratio = (ratio * U256::from(*tick_ratio)) >> 128;
+        }
+    }
+
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // Q128.128 -> Q64.64, rounding up so the result is never below the true price
+    let sqrt_price = div_round_up(ratio, U256::one() << 64)?;
+    u128::try_from(sqrt_price).ok()
+}
+
+// Greatest tick whose sqrt price is less than or equal to `sqrt_price`
+pub fn tick_at_sqrt_price(sqrt_price: u128) -> Option<i32> {
+    if !(MIN_SQRT_PRICE..=MAX_SQRT_PRICE).contains(&sqrt_price) {
+        return None;
+    }
+
+    let mut low = MIN_TICK;
+    let mut high = MAX_TICK;
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if sqrt_price_at_tick(mid)? <= sqrt_price {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    Some(low)
+}
+
+// Token A between two prices: L * (sqrt_upper - sqrt_lower) / (sqrt_lower * sqrt_upper)
+pub fn amount_a_delta(
+    sqrt_lower: u128,
+    sqrt_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u128> {
+    let (sqrt_lower, sqrt_upper) = if sqrt_lower > sqrt_upper {
+        (sqrt_upper, sqrt_lower)
+    } else {
+        (sqrt_lower, sqrt_upper)
+    };
+    if sqrt_lower == 0 {
+        return None;
+    }
+
+    let numerator = (U256::from(liquidity) << 64)
+        .checked_mul(U256::from(sqrt_upper - sqrt_lower))?;
+    let denominator = U256::from(sqrt_lower).checked_mul(U256::from(sqrt_upper))?;
+
+    let amount = if round_up {
+        div_round_up(numerator, denominator)?
+    } else {
+        numerator.checked_div(denominator)?
+    };
+    u128::try_from(amount).ok()
+}
+
+// Token B between two prices: L * (sqrt_upper - sqrt_lower)
+pub fn amount_b_delta(
+    sqrt_lower: u128,
+    sqrt_upper: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Option<u128> {
+    let (sqrt_lower, sqrt_upper) = if sqrt_lower > sqrt_upper {
+        (sqrt_upper, sqrt_lower)
+    } else {
+        (sqrt_lower, sqrt_upper)
+    };
+
+    let product = U256::from(liquidity).checked_mul(U256::from(sqrt_upper - sqrt_lower))?;
+    let amount = if round_up {
+        div_round_up(product, U256::one() << 64)?
+    } else {
+        product >> 64
+    };
+    u128::try_from(amount).ok()
+}
+
+// Price after adding `amount` of token A, rounded up: L / (L / sqrt_price + amount)
+pub fn next_sqrt_price_from_a_input(sqrt_price: u128, liquidity: u128, amount: u128) -> Option<u128> {
+    if amount == 0 {
+        return Some(sqrt_price);
+    }
+
+    let liquidity_shifted = U256::from(liquidity) << 64;
+    let denominator = liquidity_shifted
+        .checked_div(U256::from(sqrt_price))?
+        .checked_add(U256::from(amount))?;
+    let next = div_round_up(liquidity_shifted, denominator)?;
+    u128::try_from(next).ok()
+}
+
+// Price after adding `amount` of token B, rounded down: sqrt_price + amount / L
+pub fn next_sqrt_price_from_b_input(sqrt_price: u128, liquidity: u128, amount: u128) -> Option<u128> {
+    let delta = (U256::from(amount) << 64).checked_div(U256::from(liquidity))?;
+    let next = U256::from(sqrt_price).checked_add(delta)?;
+    u128::try_from(next).ok()
+}
+
+pub struct SwapStep {
+    pub sqrt_price_next: u128,
+    pub amount_in: u128,
+    pub amount_out: u128,
+    pub fee_amount: u128,
+}
+
+// One swap step within a single liquidity range, for an exact input amount.
+// `fee_bps` is taken from the input on top of `amount_in`.
+pub fn compute_swap_step(
+    sqrt_price_current: u128,
+    sqrt_price_target: u128,
+    liquidity: u128,
+    amount_remaining: u128,
+    fee_bps: u64,
+    a_to_b: bool,
+) -> Option<SwapStep> {
+    let fee_bps = fee_bps as u128;
+    let amount_remaining_less_fee = amount_remaining
+        .checked_mul(10000 - fee_bps)?
+        .checked_div(10000)?;
+
+    let amount_in_to_target = if a_to_b {
+        amount_a_delta(sqrt_price_target, sqrt_price_current, liquidity, true)?
+    } else {
+        amount_b_delta(sqrt_price_current, sqrt_price_target, liquidity, true)?
+    };
+
+    let sqrt_price_next = if amount_remaining_less_fee >= amount_in_to_target {
+        sqrt_price_target
+    } else if a_to_b {
+        next_sqrt_price_from_a_input(sqrt_price_current, liquidity, amount_remaining_less_fee)?
+    } else {
+        next_sqrt_price_from_b_input(sqrt_price_current, liquidity, amount_remaining_less_fee)?
+    };
+    let reached_target = sqrt_price_next == sqrt_price_target;
+
+    let (amount_in, amount_out) = if a_to_b {
+        let amount_in = if reached_target {
+            amount_in_to_target
+        } else {
+            amount_a_delta(sqrt_price_next, sqrt_price_current, liquidity, true)?
+        };
+        let amount_out = amount_b_delta(sqrt_price_next, sqrt_price_current, liquidity, false)?;
+        (amount_in, amount_out)
+    } else {
+        let amount_in = if reached_target {
+            amount_in_to_target
+        } else {
+            amount_b_delta(sqrt_price_current, sqrt_price_next, liquidity, true)?
+        };
+        let amount_out = amount_a_delta(sqrt_price_current, sqrt_price_next, liquidity, false)?;
+        (amount_in, amount_out)
+    };
+
+    // The remainder of a partial step is all fee, otherwise charge the fee on top
+    let fee_amount = if reached_target {
+        let numerator = amount_in.checked_mul(fee_bps)?;
+        let denominator = 10000 - fee_bps;
+        numerator.checked_add(denominator - 1)? / denominator
+    } else {
+        amount_remaining.checked_sub(amount_in)?
+    };
+
+    Some(SwapStep {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+// Fee growth per unit of liquidity, as Q64.64
+pub fn fee_growth_delta(fee_amount: u128, liquidity: u128) -> Option<u128> {
+    if liquidity == 0 {
+        return Some(0);
+    }
+    let growth = (U256::from(fee_amount) << 64).checked_div(U256::from(liquidity))?;
+    u128::try_from(growth).ok()
+}
+
+// Fees earned by `liquidity` over a Q64.64 fee growth delta
+pub fn fees_owed(liquidity: u128, fee_growth_delta: u128) -> Option<u64> {
+    let owed = U256::from(liquidity).checked_mul(U256::from(fee_growth_delta))? >> 64;
+    u64::try_from(owed).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: u128 = 1 << 64;
+
+    // (tick, ceil(sqrt(1.0001^tick) * 2^64)) computed to 100 digits
+    const SQRT_PRICES: [(i32, u128); 11] = [
+        (MIN_TICK, 4_295_048_017),
+        (-200_000, 837_899_702_510_259),
+        (-100, 18_354_745_142_194_483_564),
+        (-1, 18_445_821_805_675_392_312),
+        (0, ONE),
+        (1, 18_447_666_387_855_959_851),
+        (2, 18_448_588_748_116_922_572),
+        (100, 18_539_204_128_674_405_813),
+        (12_345, 34_195_943_348_800_206_621),
+        (200_000, 406_113_483_393_643_373_014_940),
+        (MAX_TICK, 79_226_673_515_401_279_992_447_579_062),
+    ];
+
+    #[test]
+    fn sqrt_price_at_tick_rounds_up_exactly() {
+        for (tick, expected) in SQRT_PRICES {
+            assert_eq!(sqrt_price_at_tick(tick), Some(expected), "tick {tick}");
+        }
+    }
+
+    #[test]
+    fn sqrt_price_bounds_match_tick_bounds() {
+        assert_eq!(sqrt_price_at_tick(MIN_TICK), Some(MIN_SQRT_PRICE));
+        assert_eq!(sqrt_price_at_tick(MAX_TICK), Some(MAX_SQRT_PRICE));
+        assert_eq!(sqrt_price_at_tick(MIN_TICK - 1), None);
+        assert_eq!(sqrt_price_at_tick(MAX_TICK + 1), None);
+        assert_eq!(tick_at_sqrt_price(MIN_SQRT_PRICE - 1), None);
+        assert_eq!(tick_at_sqrt_price(MAX_SQRT_PRICE + 1), None);
+    }
+
+    #[test]
+    fn tick_and_sqrt_price_round_trip() {
+        for (tick, _) in SQRT_PRICES {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            assert_eq!(tick_at_sqrt_price(sqrt_price), Some(tick));
+            if tick > MIN_TICK {
+                assert_eq!(tick_at_sqrt_price(sqrt_price - 1), Some(tick - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn sqrt_price_is_monotonic() {
+        let mut previous = 0;
+        for tick in (MIN_TICK..=MAX_TICK).step_by(997) {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            assert!(sqrt_price > previous, "tick {tick}");
+            previous = sqrt_price;
+        }
+    }
+
+    #[test]
+    fn swap_step_a_to_b_stops_short_of_target() {
+        let (liquidity, amount, fee_bps) = (1_000_000_000_000, 1_000_000, 30);
+        let target = sqrt_price_at_tick(-1_000).unwrap();
+        let step = compute_swap_step(ONE, target, liquidity, amount, fee_bps, true).unwrap();
+
+        assert!(step.sqrt_price_next < ONE && step.sqrt_price_next > target);
+        assert_eq!(step.amount_in + step.fee_amount, amount);
+        assert!(step.amount_in <= amount * 9_970 / 10_000);
+        assert_eq!(step.amount_out, amount_b_delta(step.sqrt_price_next, ONE, liquidity, false).unwrap());
+        // At a price of 1, out = L * dx / (L + dx)
+        let net = amount * 9_970 / 10_000;
+        let expected = liquidity * net / (liquidity + net);
+        assert!(expected - step.amount_out <= 1, "{} vs {expected}", step.amount_out);
+    }
+
+    #[test]
+    fn swap_step_a_to_b_reaches_target() {
+        let (liquidity, fee_bps) = (1_000_000_000_000, 30);
+        let target = sqrt_price_at_tick(-10).unwrap();
+        let to_target = amount_a_delta(target, ONE, liquidity, true).unwrap();
+        let step = compute_swap_step(ONE, target, liquidity, to_target * 2, fee_bps, true).unwrap();
+
+        assert_eq!(step.sqrt_price_next, target);
+        assert_eq!(step.amount_in, to_target);
+        assert_eq!(step.amount_out, amount_b_delta(target, ONE, liquidity, false).unwrap());
+        assert_eq!(step.fee_amount, (to_target * 30).div_ceil(9_970));
+        assert!(step.amount_in + step.fee_amount < to_target * 2);
+    }
+
+    #[test]
+    fn swap_step_b_to_a_stops_short_of_target() {
+        let (liquidity, amount, fee_bps) = (1_000_000_000_000, 1_000_000, 30);
+        let target = sqrt_price_at_tick(1_000).unwrap();
+        let step = compute_swap_step(ONE, target, liquidity, amount, fee_bps, false).unwrap();
+
+        assert!(step.sqrt_price_next > ONE && step.sqrt_price_next < target);
+        assert_eq!(step.amount_in + step.fee_amount, amount);
+        assert_eq!(step.amount_out, amount_a_delta(ONE, step.sqrt_price_next, liquidity, false).unwrap());
+        let net = amount * 9_970 / 10_000;
+        let expected = liquidity * net / (liquidity + net);
+        assert!(expected - step.amount_out <= 1, "{} vs {expected}", step.amount_out);
+    }
+
+    #[test]
+    fn swap_step_b_to_a_reaches_target_with_exact_input() {
+        let (liquidity, fee_bps) = (1_000_000_000_000, 30);
+        let target = sqrt_price_at_tick(10).unwrap();
+        let to_target = amount_b_delta(ONE, target, liquidity, true).unwrap();
+        // Exactly enough input, fee included, to reach the target
+        let amount = (to_target * 10_000).div_ceil(9_970);
+        let step = compute_swap_step(ONE, target, liquidity, amount, fee_bps, false).unwrap();
+
+        assert_eq!(step.sqrt_price_next, target);
+        assert_eq!(step.amount_in, to_target);
+        assert_eq!(step.amount_out, amount_a_delta(ONE, target, liquidity, false).unwrap());
+        assert!(step.amount_in + step.fee_amount <= amount);
+    }
+}
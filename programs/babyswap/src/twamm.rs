@@ -0,0 +1,909 @@
+// Time-weighted average market maker (TWAMM) long-term orders.
+//
+// A long-term order sells a fixed amount of one pool token at a constant rate
+// per slot until it expires. Orders are virtually executed in bulk: whenever
+// the pool is touched (a swap, a liquidity change, a limit order or DCA fill,
+// or a long-term order instruction), everything sold since the last touch is
+// first crossed between the two sides at the pool's spot price, and only the
+// remainder is swapped through the curve. Proceeds are tracked per unit of sell rate, like LP fees
+// in concentrated pools, and paid out when the order is closed.
+//
+// Orders expire on `TWAMM_INTERVAL` boundaries, at most `TWAMM_INTERVALS`
+// intervals ahead, which bounds the work one settlement has to do. Each
+// boundary keeps a ring slot until its orders are closed, so expired orders
+// can be closed by anyone, paying out to the owner.
+//
+// Unsold input and proceeds sit in token accounts at
+// `[b"twamm_vault", twamm, mint]`, recorded on the `Twamm` account.
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{transfer, Mint, Token, TokenAccount, Transfer},
+};
+
+use crate::math::U256;
+use crate::permission::{self, AllowlistEntry};
+use crate::{ErrorCode, PoolInfo};
+
+// ~30 minutes of slots
+pub const TWAMM_INTERVAL: u64 = 4_500;
+// Orders last at most a day
+pub const TWAMM_INTERVALS: usize = 48;
+
+#[zero_copy]
+#[derive(Default)]
+pub struct TwammInterval {
+    // Slot this entry expires orders at
+    pub boundary: u64,
+    // Orders expiring here that have not been closed yet
+    pub open_orders: u64,
+    // Sell rates that stop at `boundary`, Q32.32 tokens per slot
+    pub expiring_rate_a: u128,
+    pub expiring_rate_b: u128,
+    // `earnings_per_rate_*` at `boundary`, set when settlement crosses it
+    pub earnings_a: u128,
+    pub earnings_b: u128,
+}
+
+#[account(zero_copy)]
+pub struct Twamm {
+    pub pool: Pubkey,
+    pub vault_a: Pubkey,
+    pub vault_b: Pubkey,
+    // Orders have been virtually executed up to this slot
+    pub last_slot: u64,
+    pub bump: u8,
+    pub _padding: [u8; 7],
+    // Token A and token B sold per slot by all active orders, Q32.32
+    pub sell_rate_a: u128,
+    pub sell_rate_b: u128,
+    // Proceeds per unit of sell rate over the pool's lifetime, Q64.64. Sellers
+    // of A earn token B and sellers of B earn token A. Wraps like fee growth.
+    pub earnings_per_rate_a: u128,
+    pub earnings_per_rate_b: u128,
+    pub intervals: [TwammInterval; TWAMM_INTERVALS],
+}
+
+// What one settlement did to the pool
+#[derive(Default)]
+struct Execution {
+    in_a: u128,
+    out_a: u128,
+    in_b: u128,
+    out_b: u128,
+    // Swap fees on the part that went through the curve, in token A
+    fee: u128,
+    // Orders that reached their expiry
+    expired_orders: u64,
+}
+
+impl Twamm {
+    fn interval_index(boundary: u64) -> usize {
+        ((boundary / TWAMM_INTERVAL) % TWAMM_INTERVALS as u64) as usize
+    }
+
+    pub fn interval(&self, boundary: u64) -> &TwammInterval {
+        &self.intervals[Self::interval_index(boundary)]
+    }
+
+    pub fn interval_mut(&mut self, boundary: u64) -> &mut TwammInterval {
+        &mut self.intervals[Self::interval_index(boundary)]
+    }
+
+    // Start selling `amount` at `slot` until `intervals` boundaries ahead,
+    // returning the order's (sell_rate, expiry_slot, earnings_start)
+    fn add_order(&mut self, slot: u64, amount: u64, sell_a: bool, intervals: u64) -> Result<(u128, u64, u128)> {
+        let expiry_slot = (slot / TWAMM_INTERVAL + intervals) * TWAMM_INTERVAL;
+        let sell_rate = ((amount as u128) << 32) / (expiry_slot - slot) as u128;
+        require!(sell_rate > 0, ErrorCode::InvalidAmount);
+
+        let interval = self.interval_mut(expiry_slot);
+        if interval.boundary != expiry_slot {
+            // The ring slot still holds an older boundary with unclosed
+            // orders, which anyone can close now that they have expired
+            require!(interval.open_orders == 0, ErrorCode::TwammIntervalBusy);
+            *interval = TwammInterval {
+                boundary: expiry_slot,
+                ..Default::default()
+            };
+        }
+        interval.open_orders += 1;
+
+        let earnings_start = if sell_a {
+            interval.expiring_rate_a += sell_rate;
+            self.sell_rate_a = self.sell_rate_a.checked_add(sell_rate).ok_or(ErrorCode::Overflow)?;
+            self.earnings_per_rate_a
+        } else {
+            interval.expiring_rate_b += sell_rate;
+            self.sell_rate_b = self.sell_rate_b.checked_add(sell_rate).ok_or(ErrorCode::Overflow)?;
+            self.earnings_per_rate_b
+        };
+
+        Ok((sell_rate, expiry_slot, earnings_start))
+    }
+
+    // Virtually execute all orders up to `slot` against `reserves`
+    fn execute(
+        &mut self,
+        pool: &PoolInfo,
+        reserves: (u128, u128),
+        slot: u64,
+        now: i64,
+    ) -> Result<Execution> {
+        let (mut reserve_a, mut reserve_b) = reserves;
+        let mut execution = Execution::default();
+
+        while self.last_slot < slot {
+            // Nothing is selling, so no boundary ahead has anything to expire
+            if self.sell_rate_a == 0 && self.sell_rate_b == 0 {
+                self.last_slot = slot;
+                break;
+            }
+
+            let next_boundary = (self.last_slot / TWAMM_INTERVAL + 1) * TWAMM_INTERVAL;
+            let segment_end = next_boundary.min(slot);
+            let slots = (segment_end - self.last_slot) as u128;
+
+            let sold_a = self.sell_rate_a.checked_mul(slots).ok_or(ErrorCode::Overflow)? >> 32;
+            let sold_b = self.sell_rate_b.checked_mul(slots).ok_or(ErrorCode::Overflow)? >> 32;
+
+            // Cross the two sides at the spot price, then swap what is left over
+            let price = pool
+                .spot_price(reserve_a, reserve_b, now)
+                .filter(|price| *price > 0)
+                .ok_or(ErrorCode::Overflow)?;
+            let sold_a_in_b = u128::try_from((U256::from(sold_a) * U256::from(price)) >> 64)
+                .map_err(|_| ErrorCode::Overflow)?;
+
+            let (proceeds_a, proceeds_b) = if sold_a_in_b >= sold_b {
+                let crossed_a = u128::try_from((U256::from(sold_b) << 64) / U256::from(price))
+                    .map_err(|_| ErrorCode::Overflow)?;
+                let net_a = sold_a - crossed_a;
+                let (out_b, fee) = if net_a > 0 {
+                    pool.quote_swap(reserve_a, reserve_b, net_a, true, now)?
+                } else {
+                    (0, 0)
+                };
+                reserve_a += net_a;
+                reserve_b -= out_b;
+                execution.in_a += net_a;
+                execution.out_b += out_b;
+                execution.fee += fee;
+                (sold_b + out_b, crossed_a)
+            } else {
+                let net_b = sold_b - sold_a_in_b;
+                let (out_a, fee) = pool.quote_swap(reserve_b, reserve_a, net_b, false, now)?;
+                reserve_b += net_b;
+                reserve_a -= out_a;
+                execution.in_b += net_b;
+                execution.out_a += out_a;
+                execution.fee += fee;
+                (sold_a_in_b, sold_a + out_a)
+            };
+
+            if self.sell_rate_a > 0 {
+                self.earnings_per_rate_a = self
+                    .earnings_per_rate_a
+                    .wrapping_add(earnings_growth(proceeds_a, self.sell_rate_a)?);
+            }
+            if self.sell_rate_b > 0 {
+                self.earnings_per_rate_b = self
+                    .earnings_per_rate_b
+                    .wrapping_add(earnings_growth(proceeds_b, self.sell_rate_b)?);
+            }
+            self.last_slot = segment_end;
+
+            // Stop the orders expiring here and remember what they earned
+            if segment_end == next_boundary {
+                let (earnings_a, earnings_b) = (self.earnings_per_rate_a, self.earnings_per_rate_b);
+                let interval = *self.interval(next_boundary);
+                if interval.boundary == next_boundary {
+                    self.sell_rate_a -= interval.expiring_rate_a;
+                    self.sell_rate_b -= interval.expiring_rate_b;
+                    // Cancelled orders have already left `open_orders`
+                    execution.expired_orders += interval.open_orders;
+
+                    let interval = self.interval_mut(next_boundary);
+                    interval.expiring_rate_a = 0;
+                    interval.expiring_rate_b = 0;
+                    interval.earnings_a = earnings_a;
+                    interval.earnings_b = earnings_b;
+                }
+            }
+        }
+
+        Ok(execution)
+    }
+}
+
+fn earnings_growth(proceeds: u128, sell_rate: u128) -> Result<u128> {
+    u128::try_from((U256::from(proceeds) << 64) / U256::from(sell_rate))
+        .map_err(|_| ErrorCode::Overflow.into())
+}
+
+// Proceeds of `sell_rate` over an earnings-per-rate delta
+fn proceeds(sell_rate: u128, earnings_delta: u128) -> Result<u64> {
+    u64::try_from((U256::from(sell_rate) * U256::from(earnings_delta)) >> 64)
+        .map_err(|_| ErrorCode::Overflow.into())
+}
+
+#[account]
+pub struct LongTermOrder {
+    pub pool: Pubkey,
+    pub owner: Pubkey,
+    pub order_id: u64,
+    // Selling token A for token B, or the reverse
+    pub sell_a: bool,
+    // Tokens sold per slot, Q32.32
+    pub sell_rate: u128,
+    pub expiry_slot: u64,
+    // Earnings per rate of the order's side when it was placed
+    pub earnings_start: u128,
+}
+
+// Accounts needed to settle long-term orders before a pool is touched. Pool
+// vaults are in pool order, whichever way round the instruction names them.
+pub struct Settlement<'a, 'info> {
+    pub pool: &'a AccountLoader<'info, PoolInfo>,
+    pub pool_vault_a: &'a mut Box<Account<'info, TokenAccount>>,
+    pub pool_vault_b: &'a mut Box<Account<'info, TokenAccount>>,
+    pub twamm: &'a AccountLoader<'info, Twamm>,
+    pub twamm_vault_a: &'a mut Box<Account<'info, TokenAccount>>,
+    pub twamm_vault_b: &'a mut Box<Account<'info, TokenAccount>>,
+    pub token_program: &'a Program<'info, Token>,
+}
+
+impl<'a, 'info> Settlement<'a, 'info> {
+    pub fn settle(self) -> Result<()> {
+        let clock = Clock::get()?;
+        let pool_key = self.pool.key();
+        let reserves = (self.pool_vault_a.amount as u128, self.pool_vault_b.amount as u128);
+
+        // Both accounts sign the transfers below, so release them first
+        let (mint_b, pool_bump, twamm_bump, execution) = {
+            let mut pool = self.pool.load_mut()?;
+            let mut twamm = self.twamm.load_mut()?;
+            require_keys_eq!(twamm.pool, pool_key, ErrorCode::InvalidTwammAccounts);
+            require_keys_eq!(self.twamm_vault_a.key(), twamm.vault_a, ErrorCode::InvalidTwammAccounts);
+            require_keys_eq!(self.twamm_vault_b.key(), twamm.vault_b, ErrorCode::InvalidTwammAccounts);
+            let execution = twamm.execute(&pool, reserves, clock.slot, clock.unix_timestamp)?;
+            // Expired orders no longer trade, so touches stop settling for them
+            pool.long_term_orders -= execution.expired_orders;
+            (pool.mint_b, pool.bump, twamm.bump, execution)
+        };
+        let Execution { in_a, out_a, in_b, out_b, fee, .. } = execution;
+        if in_a == 0 && out_a == 0 && in_b == 0 && out_b == 0 {
+            return Ok(());
+        }
+
+        let pool_seeds: &[&[u8]] = &[b"pool", mint_b.as_ref(), &[pool_bump]];
+        let twamm_seeds: &[&[u8]] = &[b"twamm", pool_key.as_ref(), &[twamm_bump]];
+
+        // Move the net flow of each token between the TWAMM and pool vaults
+        for (pool_vault, twamm_vault, amount_in, amount_out) in [
+            (&self.pool_vault_a, &self.twamm_vault_a, in_a, out_a),
+            (&self.pool_vault_b, &self.twamm_vault_b, in_b, out_b),
+        ] {
+            let (from, to, authority, seeds, amount) = if amount_in > amount_out {
+                (twamm_vault, pool_vault, self.twamm.to_account_info(), twamm_seeds, amount_in - amount_out)
+            } else {
+                (pool_vault, twamm_vault, self.pool.to_account_info(), pool_seeds, amount_out - amount_in)
+            };
+            if amount > 0 {
+                transfer(
+                    CpiContext::new_with_signer(
+                        self.token_program.to_account_info(),
+                        Transfer {
+                            from: from.to_account_info(),
+                            to: to.to_account_info(),
+                            authority,
+                        },
+                        &[seeds],
+                    ),
+                    amount as u64,
+                )?;
+            }
+        }
+
+        self.pool_vault_a.reload()?;
+        self.pool_vault_b.reload()?;
+        self.twamm_vault_a.reload()?;
+        self.twamm_vault_b.reload()?;
+        let mut pool = self.pool.load_mut()?;
+        pool.record_price(
+            self.pool_vault_a.amount as u128,
+            self.pool_vault_b.amount as u128,
+            clock.unix_timestamp,
+        );
+        // Only the net flows went through the curve, crossed volume never
+        // touched the pool
+        pool.record_trade(in_a.abs_diff(out_a), in_b.abs_diff(out_b), fee, clock.unix_timestamp);
+
+        msg!(
+            "TWAMM settled to slot {}: pool in {} A / {} B, out {} A / {} B",
+            clock.slot, in_a, in_b, out_a, out_b
+        );
+
+        Ok(())
+    }
+}
+
+type Vault<'info> = Box<Account<'info, TokenAccount>>;
+
+// Settle long-term orders if the pool has any, with the optional TWAMM
+// accounts of the instructions that touch the pool
+pub fn settle_if_needed<'a, 'info>(
+    pool: &'a AccountLoader<'info, PoolInfo>,
+    pool_vaults: (&'a mut Vault<'info>, &'a mut Vault<'info>),
+    twamm: &'a Option<AccountLoader<'info, Twamm>>,
+    twamm_vaults: (&'a mut Option<Vault<'info>>, &'a mut Option<Vault<'info>>),
+    token_program: &'a Program<'info, Token>,
+) -> Result<()> {
+    if pool.load()?.long_term_orders == 0 {
+        return Ok(());
+    }
+
+    let (Some(twamm), Some(twamm_vault_a), Some(twamm_vault_b)) =
+        (twamm, twamm_vaults.0, twamm_vaults.1)
+    else {
+        return Err(ErrorCode::MissingTwammAccounts.into());
+    };
+
+    Settlement {
+        pool,
+        pool_vault_a: pool_vaults.0,
+        pool_vault_b: pool_vaults.1,
+        twamm,
+        twamm_vault_a,
+        twamm_vault_b,
+        token_program,
+    }
+    .settle()
+}
+
+pub fn initialize_twamm(ctx: Context<InitializeTwamm>) -> Result<()> {
+    let mut twamm = ctx.accounts.twamm.load_init()?;
+    twamm.pool = ctx.accounts.pool.key();
+    twamm.vault_a = ctx.accounts.twamm_vault_a.key();
+    twamm.vault_b = ctx.accounts.twamm_vault_b.key();
+    twamm.last_slot = Clock::get()?.slot;
+    twamm.bump = ctx.bumps.twamm;
+
+    msg!("TWAMM Initialized for pool {:?}", twamm.pool);
+
+    Ok(())
+}
+
+// Sell `amount` of one pool token evenly until `intervals` interval boundaries from now
+pub fn place_long_term_order(
+    ctx: Context<PlaceLongTermOrder>,
+    order_id: u64,
+    amount: u64,
+    sell_a: bool,
+    intervals: u64,
+) -> Result<()> {
+    permission::check_access(
+        &*ctx.accounts.pool.load()?,
+        &ctx.accounts.owner.key(),
+        &ctx.accounts.allowlist_entry,
+        &ctx.accounts.gate_token,
+    )?;
+    require!(amount > 0, ErrorCode::InvalidAmount);
+    if intervals == 0 || intervals >= TWAMM_INTERVALS as u64 {
+        return Err(ErrorCode::InvalidOrderDuration.into());
+    }
+
+    let accounts = &mut *ctx.accounts;
+    Settlement {
+        pool: &accounts.pool,
+        pool_vault_a: &mut accounts.pool_token_a,
+        pool_vault_b: &mut accounts.pool_token_b,
+        twamm: &accounts.twamm,
+        twamm_vault_a: &mut accounts.twamm_vault_a,
+        twamm_vault_b: &mut accounts.twamm_vault_b,
+        token_program: &accounts.token_program,
+    }
+    .settle()?;
+
+    let (sell_rate, expiry_slot, earnings_start) =
+        accounts.twamm.load_mut()?.add_order(Clock::get()?.slot, amount, sell_a, intervals)?;
+
+    let order = &mut accounts.order;
+    order.pool = accounts.pool.key();
+    order.owner = accounts.owner.key();
+    order.order_id = order_id;
+    order.sell_a = sell_a;
+    order.sell_rate = sell_rate;
+    order.expiry_slot = expiry_slot;
+    order.earnings_start = earnings_start;
+    accounts.pool.load_mut()?.long_term_orders += 1;
+
+    let (from, to) = if sell_a {
+        (&accounts.owner_token_a, &accounts.twamm_vault_a)
+    } else {
+        (&accounts.owner_token_b, &accounts.twamm_vault_b)
+    };
+    transfer(
+        CpiContext::new(
+            accounts.token_program.to_account_info(),
+            Transfer {
+                from: from.to_account_info(),
+                to: to.to_account_info(),
+                authority: accounts.owner.to_account_info(),
+            },
+        ),
+        amount,
+    )?;
+
+    msg!(
+        "Long-Term Order Placed: selling {} token {} until slot {}",
+        amount, if sell_a { "A" } else { "B" }, expiry_slot
+    );
+
+    Ok(())
+}
+
+// Cancel an active order or withdraw an expired one. Pays out the proceeds so
+// far and, for active orders, the unsold input. Only the owner can cancel, but
+// anyone can close an expired order to free its interval slot.
+pub fn close_long_term_order(ctx: Context<CloseLongTermOrder>) -> Result<()> {
+    let accounts = &mut *ctx.accounts;
+    Settlement {
+        pool: &accounts.pool,
+        pool_vault_a: &mut accounts.pool_token_a,
+        pool_vault_b: &mut accounts.pool_token_b,
+        twamm: &accounts.twamm,
+        twamm_vault_a: &mut accounts.twamm_vault_a,
+        twamm_vault_b: &mut accounts.twamm_vault_b,
+        token_program: &accounts.token_program,
+    }
+    .settle()?;
+
+    let slot = Clock::get()?.slot;
+    let order = &accounts.order;
+    let expired = slot >= order.expiry_slot;
+    require!(
+        expired || accounts.closer.key() == order.owner,
+        ErrorCode::Unauthorized
+    );
+    let (earned, unsold, twamm_bump) = {
+        let mut twamm = accounts.twamm.load_mut()?;
+
+        let earnings_end = if expired {
+            let interval = twamm.interval(order.expiry_slot);
+            if order.sell_a { interval.earnings_a } else { interval.earnings_b }
+        } else if order.sell_a {
+            twamm.earnings_per_rate_a
+        } else {
+            twamm.earnings_per_rate_b
+        };
+        let earned = proceeds(order.sell_rate, earnings_end.wrapping_sub(order.earnings_start))?;
+
+        let unsold = if expired {
+            0
+        } else {
+            let remaining = (order.expiry_slot - slot) as u128;
+            let unsold = order.sell_rate.checked_mul(remaining).ok_or(ErrorCode::Overflow)? >> 32;
+            if order.sell_a {
+                twamm.sell_rate_a -= order.sell_rate;
+                twamm.interval_mut(order.expiry_slot).expiring_rate_a -= order.sell_rate;
+            } else {
+                twamm.sell_rate_b -= order.sell_rate;
+                twamm.interval_mut(order.expiry_slot).expiring_rate_b -= order.sell_rate;
+            }
+            unsold as u64
+        };
+        twamm.interval_mut(order.expiry_slot).open_orders -= 1;
+
+        (earned, unsold, twamm.bump)
+    };
+    // Settlement stopped counting the order when it expired
+    if !expired {
+        accounts.pool.load_mut()?.long_term_orders -= 1;
+    }
+
+    let pool_key = accounts.pool.key();
+    let seeds: &[&[u8]] = &[b"twamm", pool_key.as_ref(), &[twamm_bump]];
+    let signer = &[seeds];
+
+    // Unsold input comes back in the sold token, proceeds in the other one
+    let (input_vault, input_owner, output_vault, output_owner) = if order.sell_a {
+        (&accounts.twamm_vault_a, &accounts.owner_token_a, &accounts.twamm_vault_b, &accounts.owner_token_b)
+    } else {
+        (&accounts.twamm_vault_b, &accounts.owner_token_b, &accounts.twamm_vault_a, &accounts.owner_token_a)
+    };
+    for (from, to, amount) in [
+        (input_vault, input_owner, unsold),
+        (output_vault, output_owner, earned),
+    ] {
+        if amount > 0 {
+            transfer(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: from.to_account_info(),
+                        to: to.to_account_info(),
+                        authority: accounts.twamm.to_account_info(),
+                    },
+                    signer,
+                ),
+                amount,
+            )?;
+        }
+    }
+
+    msg!("Long-Term Order Closed: {} proceeds, {} unsold returned", earned, unsold);
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeTwamm<'info> {
+    #[account(
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(address = pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(address = pool.load()?.mint_b @ ErrorCode::InvalidPoolTokenB)]
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+        payer = payer,
+        space = 8 + std::mem::size_of::<Twamm>()
+    )]
+    pub twamm: AccountLoader<'info, Twamm>,
+
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"twamm_vault",
+            twamm.key().as_ref(),
+            mint_a.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_a,
+        token::authority = twamm,
+    )]
+    pub twamm_vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"twamm_vault",
+            twamm.key().as_ref(),
+            mint_b.key().as_ref()
+        ],
+        bump,
+        token::mint = mint_b,
+        token::authority = twamm,
+    )]
+    pub twamm_vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: u64)]
+pub struct PlaceLongTermOrder<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        init,
+        seeds = [
+            b"long_term_order",
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            &order_id.to_le_bytes()
+        ],
+        bump,
+        payer = owner,
+        space = 8 + std::mem::size_of::<LongTermOrder>()
+    )]
+    pub order: Box<Account<'info, LongTermOrder>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: AccountLoader<'info, Twamm>,
+
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, address = twamm.load()?.vault_a @ ErrorCode::InvalidTwammAccounts)]
+    pub twamm_vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = twamm.load()?.vault_b @ ErrorCode::InvalidTwammAccounts)]
+    pub twamm_vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = owner_token_a.owner == owner.key() @ ErrorCode::InvalidUserTokenA,
+        constraint = owner_token_a.mint == pool.load()?.mint_a @ ErrorCode::InvalidUserTokenAMint
+    )]
+    pub owner_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = owner_token_b.owner == owner.key() @ ErrorCode::InvalidUserTokenB,
+        constraint = owner_token_b.mint == pool.load()?.mint_b @ ErrorCode::InvalidUserTokenBMint
+    )]
+    pub owner_token_b: Box<Account<'info, TokenAccount>>,
+
+    // Only required in permissioned pools, see `permission.rs`
+    #[account(
+        seeds = [
+            b"allowlist",
+            pool.key().as_ref(),
+            owner.key().as_ref()
+        ],
+        bump = allowlist_entry.bump,
+    )]
+    pub allowlist_entry: Option<Box<Account<'info, AllowlistEntry>>>,
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CloseLongTermOrder<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"pool",
+            pool.load()?.mint_b.as_ref()
+        ],
+        bump = pool.load()?.bump,
+    )]
+    pub pool: AccountLoader<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        has_one = pool,
+        has_one = owner @ ErrorCode::Unauthorized,
+        close = owner,
+    )]
+    pub order: Box<Account<'info, LongTermOrder>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"twamm",
+            pool.key().as_ref()
+        ],
+        bump,
+    )]
+    pub twamm: AccountLoader<'info, Twamm>,
+
+    #[account(mut, address = pool.load()?.vault_a @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = pool.load()?.vault_b @ ErrorCode::InvalidPoolVault)]
+    pub pool_token_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(mut, address = twamm.load()?.vault_a @ ErrorCode::InvalidTwammAccounts)]
+    pub twamm_vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut, address = twamm.load()?.vault_b @ ErrorCode::InvalidTwammAccounts)]
+    pub twamm_vault_b: Box<Account<'info, TokenAccount>>,
+
+    #[account(address = pool.load()?.mint_a @ ErrorCode::InvalidPoolTokenA)]
+    pub mint_a: Box<Account<'info, Mint>>,
+    #[account(address = pool.load()?.mint_b @ ErrorCode::InvalidPoolTokenB)]
+    pub mint_b: Box<Account<'info, Mint>>,
+
+    // Created if needed, so an owner can't keep an expired order open by
+    // closing their token accounts
+    #[account(
+        init_if_needed,
+        payer = closer,
+        associated_token::mint = mint_a,
+        associated_token::authority = owner,
+    )]
+    pub owner_token_a: Box<Account<'info, TokenAccount>>,
+    #[account(
+        init_if_needed,
+        payer = closer,
+        associated_token::mint = mint_b,
+        associated_token::authority = owner,
+    )]
+    pub owner_token_b: Box<Account<'info, TokenAccount>>,
+
+    /// CHECK: Order owner, receives the proceeds and the order's rent
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    // The owner, or anyone once the order has expired
+    #[account(mut)]
+    pub closer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 100 * TWAMM_INTERVAL;
+    const NOW: i64 = 1_700_000_000;
+
+    fn pool() -> PoolInfo {
+        let mut pool: PoolInfo = bytemuck::Zeroable::zeroed();
+        let key = Pubkey::default();
+        pool.initialize(key, key, key, key, 30, NOW);
+        pool
+    }
+
+    fn twamm() -> Twamm {
+        let mut twamm: Twamm = bytemuck::Zeroable::zeroed();
+        twamm.last_slot = START;
+        twamm
+    }
+
+    struct Order {
+        sell_a: bool,
+        sell_rate: u128,
+        expiry_slot: u64,
+        earnings_start: u128,
+    }
+
+    impl Order {
+        fn place(twamm: &mut Twamm, slot: u64, amount: u64, sell_a: bool, intervals: u64) -> Self {
+            let (sell_rate, expiry_slot, earnings_start) = twamm.add_order(slot, amount, sell_a, intervals).unwrap();
+            Order { sell_a, sell_rate, expiry_slot, earnings_start }
+        }
+
+        // Proceeds once expired, as `close_long_term_order` pays them
+        fn proceeds(&self, twamm: &Twamm) -> u64 {
+            let interval = twamm.interval(self.expiry_slot);
+            let end = if self.sell_a { interval.earnings_a } else { interval.earnings_b };
+            proceeds(self.sell_rate, end.wrapping_sub(self.earnings_start)).unwrap()
+        }
+    }
+
+    #[test]
+    fn opposite_orders_cross_without_touching_the_pool() {
+        let (pool, mut twamm) = (pool(), twamm());
+        let amount = 9_000_000;
+        let a = Order::place(&mut twamm, START, amount, true, 2);
+        let b = Order::place(&mut twamm, START, amount, false, 2);
+
+        // Equal reserves price A at 1 B, so both sides fully cross
+        let reserves = (1_000_000_000_000, 1_000_000_000_000);
+        let execution = twamm.execute(&pool, reserves, START + 5 * TWAMM_INTERVAL, NOW).unwrap();
+        assert_eq!((execution.in_a, execution.out_a, execution.in_b, execution.out_b), (0, 0, 0, 0));
+        assert_eq!(execution.fee, 0);
+        assert_eq!(execution.expired_orders, 2);
+
+        for order in [a, b] {
+            let proceeds = order.proceeds(&twamm);
+            assert!(proceeds <= amount && proceeds >= amount - 2, "{proceeds}");
+        }
+    }
+
+    #[test]
+    fn expired_orders_stop_selling() {
+        let (pool, mut twamm) = (pool(), twamm());
+        let amount = 3_000_000_000;
+        let slot = START + 100;
+        twamm.last_slot = slot;
+        let order = Order::place(&mut twamm, slot, amount, true, 3);
+        assert_eq!(order.expiry_slot, START + 3 * TWAMM_INTERVAL);
+
+        let reserves = (1_000_000_000_000, 1_000_000_000_000);
+        let execution = twamm.execute(&pool, reserves, order.expiry_slot + 5 * TWAMM_INTERVAL, NOW).unwrap();
+        assert_eq!(execution.expired_orders, 1);
+        assert_eq!(twamm.sell_rate_a, 0);
+        assert_eq!(twamm.last_slot, order.expiry_slot + 5 * TWAMM_INTERVAL);
+        // Sold everything but per-segment rounding
+        assert!(execution.in_a <= amount as u128 && execution.in_a >= amount as u128 - 3);
+        assert_eq!(execution.out_a + execution.in_b, 0);
+
+        let interval = *twamm.interval(order.expiry_slot);
+        assert_eq!(interval.expiring_rate_a, 0);
+        assert_eq!(interval.earnings_a, twamm.earnings_per_rate_a);
+        assert!(order.proceeds(&twamm) as u128 <= execution.out_b);
+
+        // Nothing left to sell, so later settlements change nothing
+        let later = twamm.execute(&pool, reserves, twamm.last_slot + 10 * TWAMM_INTERVAL, NOW).unwrap();
+        assert_eq!((later.in_a, later.out_b, later.expired_orders), (0, 0, 0));
+    }
+
+    #[test]
+    fn unclosed_expired_orders_hold_their_ring_slot() {
+        let (pool, mut twamm) = (pool(), twamm());
+        let order = Order::place(&mut twamm, START, 1_000_000, true, 1);
+        twamm
+            .execute(&pool, (1_000_000_000, 1_000_000_000), START + TWAMM_INTERVALS as u64 * TWAMM_INTERVAL, NOW)
+            .unwrap();
+
+        // The boundary a full ring later maps onto the expired order's slot
+        let slot = twamm.last_slot;
+        assert!(twamm.add_order(slot, 1_000_000, true, 1).is_err());
+
+        twamm.interval_mut(order.expiry_slot).open_orders -= 1;
+        assert!(twamm.add_order(slot, 1_000_000, true, 1).is_ok());
+    }
+
+    #[test]
+    fn vaults_cover_all_proceeds() {
+        let (pool, mut twamm) = (pool(), twamm());
+        let (mut reserve_a, mut reserve_b) = (50_000_000_000u128, 20_000_000_000u128);
+        let (mut vault_a, mut vault_b) = (0u128, 0u128);
+        let mut orders = vec![];
+
+        let placements = [
+            (START, 1_000_000_000u64, true, 5),
+            (START, 300_000_000, false, 2),
+            (START + 7_000, 50_000_000, true, 7),
+            (START + 11_111, 2_000_000_000, false, 4),
+            (START + 20_000, 123_456_789, true, 1),
+        ];
+        // Settle at every placement and every 1_234 slots until all have expired
+        let end = START + 12 * TWAMM_INTERVAL;
+        let mut slots: Vec<u64> = (START..end).step_by(1_234).chain(placements.iter().map(|p| p.0)).collect();
+        slots.push(end);
+        slots.sort_unstable();
+        slots.dedup();
+
+        for slot in slots {
+            let execution = twamm.execute(&pool, (reserve_a, reserve_b), slot, NOW).unwrap();
+            reserve_a = reserve_a + execution.in_a - execution.out_a;
+            reserve_b = reserve_b + execution.in_b - execution.out_b;
+            vault_a = vault_a + execution.out_a - execution.in_a;
+            vault_b = vault_b + execution.out_b - execution.in_b;
+
+            for &(_, amount, sell_a, intervals) in placements.iter().filter(|placement| placement.0 == slot) {
+                orders.push(Order::place(&mut twamm, slot, amount, sell_a, intervals));
+                if sell_a {
+                    vault_a += amount as u128;
+                } else {
+                    vault_b += amount as u128;
+                }
+            }
+        }
+
+        assert_eq!(orders.len(), placements.len());
+        assert_eq!((twamm.sell_rate_a, twamm.sell_rate_b), (0, 0));
+        let owed_a: u128 = orders.iter().filter(|order| !order.sell_a).map(|order| order.proceeds(&twamm) as u128).sum();
+        let owed_b: u128 = orders.iter().filter(|order| order.sell_a).map(|order| order.proceeds(&twamm) as u128).sum();
+        assert!(owed_a <= vault_a, "owe {owed_a} A, hold {vault_a}");
+        assert!(owed_b <= vault_b, "owe {owed_b} B, hold {vault_b}");
+        // Only rounding dust is left behind
+        assert!(vault_a - owed_a < 100 && vault_b - owed_b < 100, "{vault_a} {owed_a} {vault_b} {owed_b}");
+    }
+}
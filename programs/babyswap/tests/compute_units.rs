@@ -0,0 +1,279 @@
+// Compute units used by `buy` and `sell`, against the ceilings below and the
+// original Borsh program.
+//
+// Runs SBF builds, so build the program and the baseline commit first and
+// point `SBF_OUT_DIR` at `target/deploy` if they aren't found there:
+//
+//     git worktree add ../soondex-baseline a8a7a24
+//     (cd ../soondex-baseline && anchor build)
+//     cp ../soondex-baseline/target/deploy/soondex.so target/deploy/soondex_baseline.so
+//     anchor build
+//     cargo test -p soondex --test compute_units -- --ignored --nocapture
+//
+// The pool and token accounts are written straight into the test bank, which
+// keeps Token Metadata out of the setup. Both builds declare the same program
+// id, so each runs in its own bank.
+
+use anchor_lang::{AnchorSerialize, Discriminator, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::{self, get_associated_token_address};
+use solana_program_test::{BanksClient, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_program, sysvar,
+    transaction::Transaction,
+};
+use soondex::migrate::BASELINE_POOL_SPACE;
+use soondex::{accounts, instruction, BuyInstructionData, PoolInfo, SellInstructionData, ID};
+
+const RESERVE: u64 = 1_000_000_000_000;
+const USER_BALANCE: u64 = 1_000_000_000;
+const SWAP_AMOUNT: u64 = 1_000_000;
+// Regression ceilings for the zero-copy build
+const BUY_CU_CEILING: u64 = 60_000;
+const SELL_CU_CEILING: u64 = 60_000;
+
+#[derive(Clone, Copy)]
+enum Build {
+    // The original program, with Borsh pools and reserves in associated token accounts
+    Baseline,
+    ZeroCopy,
+}
+
+impl Build {
+    fn program_name(self) -> &'static str {
+        match self {
+            Build::Baseline => "soondex_baseline",
+            Build::ZeroCopy => "soondex",
+        }
+    }
+}
+
+struct Pool {
+    key: Pubkey,
+    mint_a: Pubkey,
+    mint_b: Pubkey,
+    vault_a: Pubkey,
+    vault_b: Pubkey,
+}
+
+fn account(data: Vec<u8>, owner: Pubkey) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn mint_account() -> Account {
+    let mut data = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint {
+        decimals: 6,
+        is_initialized: true,
+        ..Default::default()
+    }
+    .pack_into_slice(&mut data);
+    account(data, spl_token::ID)
+}
+
+fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+    let mut data = vec![0; spl_token::state::Account::LEN];
+    spl_token::state::Account {
+        mint,
+        owner,
+        amount,
+        state: spl_token::state::AccountState::Initialized,
+        ..Default::default()
+    }
+    .pack_into_slice(&mut data);
+    account(data, spl_token::ID)
+}
+
+// A funded constant product pool with a 30 bps fee, and a user holding both tokens
+fn setup(program_test: &mut ProgramTest, build: Build, user: &Pubkey) -> Pool {
+    let (mint_a, mint_b) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (key, bump) = Pubkey::find_program_address(&[b"pool", mint_b.as_ref()], &ID);
+    let (lp_mint, lp_mint_bump) = Pubkey::find_program_address(&[b"lp_mint", key.as_ref()], &ID);
+
+    let mut data = PoolInfo::DISCRIMINATOR.to_vec();
+    let (vault_a, vault_b) = match build {
+        Build::Baseline => {
+            for field in [key, mint_a, mint_b, lp_mint] {
+                data.extend_from_slice(field.as_ref());
+            }
+            data.extend_from_slice(&30u64.to_le_bytes());
+            data.extend_from_slice(&(RESERVE as u128).to_le_bytes());
+            data.resize(BASELINE_POOL_SPACE, 0);
+            (get_associated_token_address(&key, &mint_a), get_associated_token_address(&key, &mint_b))
+        }
+        Build::ZeroCopy => {
+            let (vault_a, vault_a_bump) = Pubkey::find_program_address(&[b"vault", key.as_ref(), mint_a.as_ref()], &ID);
+            let (vault_b, vault_b_bump) = Pubkey::find_program_address(&[b"vault", key.as_ref(), mint_b.as_ref()], &ID);
+            let mut pool: PoolInfo = bytemuck::Zeroable::zeroed();
+            pool.initialize(key, mint_a, mint_b, lp_mint, 30, 0);
+            pool.set_bumps(bump, lp_mint_bump);
+            pool.set_vaults(vault_a, vault_a_bump, vault_b, vault_b_bump);
+            pool.total_liquidity = RESERVE as u128;
+            data.extend_from_slice(bytemuck::bytes_of(&pool));
+            (vault_a, vault_b)
+        }
+    };
+
+    program_test.add_account(key, account(data, ID));
+    program_test.add_account(mint_a, mint_account());
+    program_test.add_account(mint_b, mint_account());
+    program_test.add_account(lp_mint, mint_account());
+    program_test.add_account(vault_a, token_account(mint_a, key, RESERVE));
+    program_test.add_account(vault_b, token_account(mint_b, key, RESERVE));
+    for mint in [mint_a, mint_b] {
+        program_test.add_account(
+            get_associated_token_address(user, &mint),
+            token_account(mint, *user, USER_BALANCE),
+        );
+    }
+
+    Pool { key, mint_a, mint_b, vault_a, vault_b }
+}
+
+fn buy(pool: &Pool, user: &Pubkey) -> Instruction {
+    let accounts = accounts::BuyInstruction {
+        pool: pool.key,
+        mint_a: pool.mint_a,
+        mint_b: pool.mint_b,
+        pool_token_a_ata: pool.vault_a,
+        pool_token_b_ata: pool.vault_b,
+        user_token_a_ata: get_associated_token_address(user, &pool.mint_a),
+        user_token_b_ata: get_associated_token_address(user, &pool.mint_b),
+        config: None,
+        creator_fee_vault: None,
+        twamm: None,
+        twamm_vault_a: None,
+        twamm_vault_b: None,
+        allowlist_entry: None,
+        gate_token: None,
+        user: *user,
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+        associated_token_program: associated_token::ID,
+    };
+    let swap = BuyInstructionData { amount: SWAP_AMOUNT, slippage: 100, is_buy: true, referral_bps: 0 };
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction::Buy { swap }.data(),
+    }
+}
+
+// The sell context names the pool's mints and vaults the other way round
+fn sell(pool: &Pool, user: &Pubkey) -> Instruction {
+    let accounts = accounts::SellInstruction {
+        pool: pool.key,
+        mint_a: pool.mint_b,
+        mint_b: pool.mint_a,
+        pool_token_a_ata: pool.vault_b,
+        pool_token_b_ata: pool.vault_a,
+        user_token_a_ata: get_associated_token_address(user, &pool.mint_b),
+        user_token_b_ata: get_associated_token_address(user, &pool.mint_a),
+        config: None,
+        creator_fee_vault: None,
+        twamm: None,
+        twamm_vault_a: None,
+        twamm_vault_b: None,
+        allowlist_entry: None,
+        gate_token: None,
+        user: *user,
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+        token_program: spl_token::ID,
+        associated_token_program: associated_token::ID,
+    };
+    let swap = SellInstructionData { amount: SWAP_AMOUNT, slippage: 100, is_buy: false, referral_bps: 0 };
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction::Sell { swap }.data(),
+    }
+}
+
+// The baseline program's `buy` and `sell` take the same accounts without the
+// optional ones, so `sell` mirrors the mints and vaults the same way
+fn baseline_swap(pool: &Pool, user: &Pubkey, is_buy: bool) -> Instruction {
+    let (mint_a, mint_b, vault_a, vault_b) = if is_buy {
+        (pool.mint_a, pool.mint_b, pool.vault_a, pool.vault_b)
+    } else {
+        (pool.mint_b, pool.mint_a, pool.vault_b, pool.vault_a)
+    };
+    let accounts = vec![
+        AccountMeta::new(pool.key, false),
+        AccountMeta::new(mint_a, false),
+        AccountMeta::new(mint_b, false),
+        AccountMeta::new(vault_a, false),
+        AccountMeta::new(vault_b, false),
+        AccountMeta::new(get_associated_token_address(user, &mint_a), false),
+        AccountMeta::new(get_associated_token_address(user, &mint_b), false),
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(sysvar::rent::ID, false),
+        AccountMeta::new_readonly(system_program::ID, false),
+        AccountMeta::new_readonly(spl_token::ID, false),
+        AccountMeta::new_readonly(associated_token::ID, false),
+    ];
+    let mut data = if is_buy {
+        instruction::Buy::DISCRIMINATOR.to_vec()
+    } else {
+        instruction::Sell::DISCRIMINATOR.to_vec()
+    };
+    // Borsh lays a tuple out like the baseline's `{ amount, slippage, is_buy }`
+    (SWAP_AMOUNT, 100u64, is_buy).serialize(&mut data).unwrap();
+    Instruction { program_id: ID, accounts, data }
+}
+
+async fn compute_units(banks_client: &mut BanksClient, user: &Keypair, instruction: Instruction) -> u64 {
+    let blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&user.pubkey()), &[user], blockhash);
+    let result = banks_client.process_transaction_with_metadata(transaction).await.unwrap();
+    let metadata = result.metadata.unwrap();
+    if let Err(err) = result.result {
+        panic!("{err}: {:#?}", metadata.log_messages);
+    }
+    metadata.compute_units_consumed
+}
+
+// (buy, sell) compute units of `build`
+async fn measure(build: Build) -> (u64, u64) {
+    let mut program_test = ProgramTest::new(build.program_name(), ID, None);
+    program_test.prefer_bpf(true);
+    let user = Keypair::new();
+    program_test.add_account(
+        user.pubkey(),
+        Account { lamports: 1_000_000_000, ..account(vec![], system_program::ID) },
+    );
+    let pool = setup(&mut program_test, build, &user.pubkey());
+    let (mut banks_client, _, _) = program_test.start().await;
+
+    let (buy, sell) = match build {
+        Build::Baseline => (baseline_swap(&pool, &user.pubkey(), true), baseline_swap(&pool, &user.pubkey(), false)),
+        Build::ZeroCopy => (buy(&pool, &user.pubkey()), sell(&pool, &user.pubkey())),
+    };
+    let buy_units = compute_units(&mut banks_client, &user, buy).await;
+    let sell_units = compute_units(&mut banks_client, &user, sell).await;
+    (buy_units, sell_units)
+}
+
+#[tokio::test]
+#[ignore = "needs SBF builds of the program and the baseline, see the top of this file"]
+async fn buy_and_sell_compute_units() {
+    let (baseline_buy, baseline_sell) = measure(Build::Baseline).await;
+    let (buy_units, sell_units) = measure(Build::ZeroCopy).await;
+
+    println!("buy: {baseline_buy} -> {buy_units} CU");
+    println!("sell: {baseline_sell} -> {sell_units} CU");
+    assert!(buy_units <= BUY_CU_CEILING, "buy used {buy_units} CU, ceiling {BUY_CU_CEILING}");
+    assert!(sell_units <= SELL_CU_CEILING, "sell used {sell_units} CU, ceiling {SELL_CU_CEILING}");
+}